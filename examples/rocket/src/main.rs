@@ -5,15 +5,15 @@ extern crate rocket;
 
 use rocket::response::content::Html;
 use rocket::response::Redirect;
-use wartid_client::handlers;
 use wartid_client::*;
 
 #[get("/")]
 fn home(user: Result<&WartIDSession, WartIDSessionError>) -> Html<String> {
     match user {
         Ok(user) => Html(format!(
-            r#"Logged in as {} (@{} - {:?})<br/><a href="/logout">Log out</a>"#,
-            user.name, user.id, user.email,
+            r#"Logged in as {} (@{} - {:?})<br/>
+            <form method="post" action="/oauth2/wartid/logout"><button>Log out</button></form>"#,
+            user.name, user.sub(), user.email,
         )),
         Err(e) => Html(format!(
             r#"Disconnected ({:?})<br/><a href="/oauth2/wartid/login">Connect</a>"#,
@@ -29,20 +29,15 @@ fn very_secret_panel(user: WartIDSessionOrRedirect) -> Result<String, Redirect>
     Ok(format!("Hello {}", &user.name))
 }
 
-#[get("/logout")]
-fn logout() -> handlers::Logout {
-    handlers::Logout(None)
-}
-
 #[launch]
 fn rocket() -> _ {
-    let client_state = wartid_client::WIDContext {
-        urls: wartid_client::WIDContextUrls::from_base_url("https://edgar.bzh:8000"),
-        credentials: Default::default(),
-    };
-
-    rocket::ignite()
-        .manage(client_state)
-        .mount("/", routes![home, very_secret_panel, logout])
-        .mount("/oauth2/wartid", wartid_client::rocket::routes(true))
+    let client_state = wartid_client::WIDContext::new(
+        wartid_client::WIDContextUrls::from_base_url("https://edgar.bzh:8000"),
+        Default::default(),
+    );
+
+    // Checks the configuration, and mounts the routes where its URLs point to
+    let rocket = rocket::ignite().mount("/", routes![home, very_secret_panel]);
+    wartid_client::rocket::mount(rocket, client_state, handlers::Login::basic().with_email())
+        .expect("invalid WartID configuration")
 }
@@ -0,0 +1,162 @@
+//! Procedural macros of `wartid-client`, re-exported by its `macros` feature
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, AttributeArgs, FnArg, ItemFn, Lit, Meta, NestedMeta, Pat, ReturnType, Type,
+};
+
+/// Protects a Rocket route: users without a session are redirected to the login page, and the
+/// route gets the session through its `&WartIDSession` parameter
+///
+/// It must be placed above the route attribute. With `scope = "..."`, users whose session wasn't
+/// granted the scope get a `403 Forbidden`.
+///
+/// ```ignore
+/// #[wartid_protected(scope = "email")]
+/// #[get("/settings")]
+/// fn settings(session: &WartIDSession) -> String {
+///     format!("Your email: {:?}", session.email)
+/// }
+/// ```
+///
+/// The route is rewritten to take a `WartIDSessionOrRedirect` and return a
+/// `Result<_, wartid_client::rocket::Rejection>`, see `WartIDSessionOrRedirect::protected`.
+#[proc_macro_attribute]
+pub fn wartid_protected(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(item as ItemFn);
+
+    match protect(args, item) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn protect(args: AttributeArgs, item: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let scope = match scope_arg(args)? {
+        Some(scope) => quote!(::std::option::Option::Some(#scope)),
+        None => quote!(::std::option::Option::None),
+    };
+
+    let mut session = None;
+    let mut params = Vec::new();
+    let mut args = Vec::new();
+    for (i, input) in item.sig.inputs.iter().enumerate() {
+        let typed = match input {
+            FnArg::Typed(typed) => typed,
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new(receiver.span(), "routes can't take `self`"))
+            }
+        };
+        let ident = match &*typed.pat {
+            Pat::Ident(pat) => pat.ident.clone(),
+            pat => {
+                return Err(syn::Error::new(
+                    pat.span(),
+                    "protected routes only take plain identifiers as parameters",
+                ))
+            }
+        };
+
+        if is_session(&typed.ty) {
+            if session.is_some() {
+                return Err(syn::Error::new(
+                    typed.span(),
+                    "more than one session parameter",
+                ));
+            }
+            session = Some((i, ident.clone()));
+        } else {
+            params.push(input.clone());
+        }
+        args.push(ident);
+    }
+
+    let (position, session) = session.ok_or_else(|| {
+        syn::Error::new(
+            item.sig.span(),
+            "protected routes must take a `&WartIDSession` parameter",
+        )
+    })?;
+    let guard = format_ident!("__wartid_session");
+    params.insert(
+        position,
+        syn::parse_quote!(#guard: ::wartid_client::WartIDSessionOrRedirect),
+    );
+
+    let output = match &item.sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+
+    // The original handler, kept as is so `return`s and `?`s in it keep working
+    let mut inner = item.clone();
+    inner.attrs.retain(|attr| attr.path.is_ident("allow"));
+    inner.vis = syn::Visibility::Inherited;
+    inner.sig.ident = format_ident!("__wartid_protected_{}", item.sig.ident);
+    let inner_ident = &inner.sig.ident;
+    let call = match item.sig.asyncness {
+        Some(_) => quote!(#inner_ident(#(#args),*).await),
+        None => quote!(#inner_ident(#(#args),*)),
+    };
+
+    let attrs = &item.attrs;
+    let vis = &item.vis;
+    let asyncness = &item.sig.asyncness;
+    let ident = &item.sig.ident;
+    let generics = &item.sig.generics;
+    let where_clause = &item.sig.generics.where_clause;
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis #asyncness fn #ident #generics(#(#params),*)
+            -> ::std::result::Result<#output, ::wartid_client::rocket::Rejection>
+            #where_clause
+        {
+            #inner
+
+            let #session = #guard.protected(#scope)?;
+            ::std::result::Result::Ok(#call)
+        }
+    })
+}
+
+/// Reads the `scope = "..."` argument, if any
+fn scope_arg(args: AttributeArgs) -> syn::Result<Option<String>> {
+    let mut scope = None;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(meta)) if meta.path.is_ident("scope") => {
+                match meta.lit {
+                    Lit::Str(lit) if scope.is_none() => scope = Some(lit.value()),
+                    Lit::Str(lit) => {
+                        return Err(syn::Error::new(lit.span(), "`scope` given twice"))
+                    }
+                    lit => return Err(syn::Error::new(lit.span(), "expected a string")),
+                }
+            }
+            arg => return Err(syn::Error::new(arg.span(), "expected `scope = \"...\"`")),
+        }
+    }
+
+    Ok(scope)
+}
+
+/// Whether the parameter is the session, i.e. a `&WartIDSession`
+fn is_session(ty: &Type) -> bool {
+    let reference = match ty {
+        Type::Reference(reference) => reference,
+        _ => return false,
+    };
+
+    match &*reference.elem {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map_or(false, |segment| segment.ident == "WartIDSession"),
+        _ => false,
+    }
+}
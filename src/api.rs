@@ -1,24 +1,235 @@
-use crate::{WIDContext, WartIDSession};
+use crate::jwt;
+use crate::{Scope, WIDContext, WIDContextEndpoints, WartIDError, WartIDSession};
 use chrono::{TimeZone, Utc};
 use reqwest::Url;
-use uuid::Uuid;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
 
+mod builder;
+mod cache;
+
+pub use self::builder::{ClientBuildError, ClientBuilder};
+use self::cache::UserinfoCache;
+
+#[derive(Clone, Debug)]
 pub struct Client {
+    /// Issuer identifier of WartID, if known
+    issuer: Option<String>,
+    url_authorize: Url,
     url_token: Url,
     url_userinfo: Url,
+    url_jwks: Option<Url>,
+    url_introspection: Option<Url>,
+    url_revocation: Option<Url>,
     client: reqwest::Client,
+
+    /// Timeout of each request
+    timeout: Option<Duration>,
+
+    /// Number of retries of requests failing to connect or timing out
+    retries: u32,
+
+    /// Tokens are refreshed when they expire in less than this
+    leeway: Duration,
+
+    /// Whether access tokens are opaque, rather than JWTs
+    opaque_tokens: bool,
+
+    /// How long the JWKS is cached
+    jwks_ttl: Duration,
+
+    /// Cached JWKS along with when it was fetched, shared by the clones of the client
+    jwks_cache: Arc<Mutex<Option<(Instant, Arc<jwt::Jwks>)>>>,
+
+    /// Userinfo cache shared by the clones of the client, if enabled
+    userinfo_cache: Option<Arc<UserinfoCache>>,
 }
 
-impl Default for Client {
-    fn default() -> Self {
+/// Default of [ClientBuilder::jwks_ttl]
+pub(crate) const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Minimum delay between two JWKS fetches caused by tokens signed with unknown keys, so forged
+/// `kid`s can't make the client hammer WartID
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+
+impl Client {
+    pub(crate) fn from_endpoints(endpoints: &WIDContextEndpoints, client: reqwest::Client) -> Self {
+        let parse = |url: &str| Url::parse(url).expect("invalid WartID endpoint URL");
+
         Self {
-            url_token: Url::parse("https://id.wp-corp.eu.org/oauth2/token").unwrap(),
-            url_userinfo: Url::parse("https://id.wp-corp.eu.org/oauth2/userinfo").unwrap(),
-            client: reqwest::Client::builder().build().unwrap(),
+            issuer: None,
+            url_authorize: parse(&endpoints.authorize),
+            url_token: parse(&endpoints.token),
+            url_userinfo: parse(&endpoints.userinfo),
+            url_jwks: None,
+            url_introspection: None,
+            url_revocation: None,
+            client,
+            timeout: None,
+            retries: 0,
+            leeway: Duration::from_secs(0),
+            opaque_tokens: false,
+            jwks_ttl: JWKS_TTL,
+            jwks_cache: Arc::default(),
+            userinfo_cache: None,
+        }
+    }
+
+    /// Client for the WartID instance at the given endpoints, see [builder][Client::builder] for
+    /// more options
+    ///
+    /// Panics if an endpoint isn't a valid URL, which [ClientBuilder::build] reports instead.
+    pub fn new(endpoints: WIDContextEndpoints, http_client: reqwest::Client) -> Self {
+        Self::from_endpoints(&endpoints, http_client)
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Refreshes access tokens this long before they actually expire
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Treats access tokens as opaque: instead of decoding them, their expiry is tracked from the
+    /// `expires_in` returned by WartID when they're issued
+    pub fn with_opaque_tokens(mut self) -> Self {
+        self.opaque_tokens = true;
+        self
+    }
+
+    /// Client for a local development instance of WartID, accepting self-signed certificates
+    ///
+    /// **Never use this in production**: TLS certificates aren't verified at all.
+    #[cfg(feature = "insecure-dev")]
+    pub fn insecure_dev(endpoints: WIDContextEndpoints) -> Self {
+        log::warn!(
+            "[Client::insecure_dev] TLS certificates of {} are NOT verified, this must never be \
+            used in production",
+            endpoints.token,
+        );
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+
+        Self::from_endpoints(&endpoints, client)
+    }
+
+    pub(crate) fn leeway(&self) -> Duration {
+        self.leeway
+    }
+
+    pub(crate) fn authorize_url(&self) -> &Url {
+        &self.url_authorize
+    }
+
+    /// Issuer identifier of WartID, if [set][ClientBuilder::issuer] or discovered
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    /// JWKS endpoint, if set
+    pub fn jwks_url(&self) -> Option<&Url> {
+        self.url_jwks.as_ref()
+    }
+
+    /// Token introspection endpoint, if set
+    pub fn introspection_url(&self) -> Option<&Url> {
+        self.url_introspection.as_ref()
+    }
+
+    /// Token revocation endpoint, if set
+    pub fn revocation_url(&self) -> Option<&Url> {
+        self.url_revocation.as_ref()
+    }
+
+    /// Sends a request, retrying it if the client has [retries][ClientBuilder::retries] left when
+    /// it fails to connect, times out, or is rate limited for a short enough time
+    async fn send(
+        &self,
+        mut request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, WartIDError> {
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+
+        let mut retries = self.retries;
+        loop {
+            // Requests with a streamed body can't be cloned, and thus retried
+            let retry = match retries {
+                0 => None,
+                _ => request.try_clone(),
+            };
+
+            match request.send().await {
+                Err(err) if (err.is_connect() || err.is_timeout()) && retry.is_some() => {
+                    log::warn!("[Client::send] retrying: {}", err);
+                    retries -= 1;
+                    request = retry.unwrap();
+                }
+                Ok(response) if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    let retry_after = retry_after(&response);
+                    let delay = retry_after.unwrap_or(DEFAULT_RETRY_AFTER);
+
+                    match retry {
+                        Some(retry) if delay <= MAX_RETRY_AFTER => {
+                            log::warn!("[Client::send] rate limited, retrying in {:?}", delay);
+                            tokio::time::sleep(delay).await;
+                            retries -= 1;
+                            request = retry;
+                        }
+                        _ => return Err(WartIDError::RateLimited { retry_after }),
+                    }
+                }
+                result => return Ok(result?),
+            }
         }
     }
 }
 
+/// Delay before retrying rate limited requests without a `Retry-After` header
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Longest `Retry-After` waited for before retrying, as users are kept waiting meanwhile
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Delay of the `Retry-After` header of a response, given in seconds or as an HTTP date
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Client for the main WartID instance, going through the proxies set in the standard
+/// `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment variables if any
+impl Default for Client {
+    fn default() -> Self {
+        Self::from_endpoints(
+            &WIDContextEndpoints::default(),
+            reqwest::Client::builder().build().unwrap(),
+        )
+    }
+}
+
 #[derive(serde::Serialize)]
 pub struct TokenRequestData<'a> {
     grant_type: &'static str,
@@ -34,49 +245,215 @@ pub struct TokenRequestData<'a> {
 #[derive(Debug, serde::Deserialize)]
 pub struct TokenResponse {
     pub access_token: String,
-    expires_in: u64,
+    #[serde(default)]
+    expires_in: Option<u64>,
+    #[serde(default)]
     token_type: String,
+    /// Only returned when the `offline_access` scope is granted
+    #[serde(default)]
     pub refresh_token: Option<String>,
+    /// Only returned when the `openid` scope is granted
+    #[serde(default)]
+    pub id_token: Option<String>,
+    /// Granted scopes, space-separated. Only returned by WartID when they differ from the
+    /// requested ones.
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+impl TokenResponse {
+    /// Lifetime of the access token in seconds, if WartID gave it
+    pub fn expires_in(&self) -> Option<u64> {
+        self.expires_in
+    }
+
+    /// Type of the access token, `Bearer` in practice, or empty if WartID omitted it
+    pub fn token_type(&self) -> &str {
+        &self.token_type
+    }
+
+    /// Fails unless the access token is a bearer one, the only type the crate sends. The type is
+    /// case-insensitive, and tokens without one are taken as bearer tokens.
+    pub(crate) fn check_token_type(self) -> Result<Self, WartIDError> {
+        if self.token_type.is_empty() || self.token_type.eq_ignore_ascii_case("bearer") {
+            Ok(self)
+        } else {
+            Err(WartIDError::UnsupportedTokenType(self.token_type))
+        }
+    }
+
+    /// Expiry timestamp of the access token, computed from `expires_in` or else read from the
+    /// token itself if it's a JWT
+    pub(crate) fn expires_at(&self) -> Option<i64> {
+        match self.expires_in {
+            Some(expires_in) => Some(Utc::now().timestamp() + expires_in as i64),
+            None => jwt::expiry(&self.access_token),
+        }
+    }
 }
 
+/// Error response of the token endpoint
 #[derive(serde::Deserialize)]
+struct OAuthErrorResponse {
+    error: String,
+}
+
+/// Identity of the user, as answered by the userinfo endpoint of WartID
+///
+/// Returned by [Client::request_userinfo], it's also what sessions are built from.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct UserInfoResponse {
-    sub: Uuid,
+    sub: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preferred_username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    picture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email_verified: Option<bool>,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl UserInfoResponse {
+    /// Subject identifier, which OpenID Connect defines as an opaque string
+    pub fn sub(&self) -> &str {
+        &self.sub
+    }
+
+    /// Full name of the user, empty if WartID didn't give any
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Username the user goes by, which isn't guaranteed to be unique nor stable
+    pub fn preferred_username(&self) -> Option<&str> {
+        self.preferred_username.as_deref()
+    }
+
+    /// URL of the user's profile picture (`picture` claim)
+    pub fn picture(&self) -> Option<&str> {
+        self.picture.as_deref()
+    }
+
+    /// Email address of the user, only given if the [email scope][crate::Scope::Email] was granted
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
+    /// Whether WartID verified the user owns the [email][UserInfoResponse::email] address
+    pub fn email_verified(&self) -> bool {
+        self.email_verified.unwrap_or(false)
+    }
+
+    /// Fields not known by this crate, like app-specific claims
+    pub fn extra(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.extra
+    }
+
+    /// All the claims, known ones included
+    #[cfg(feature = "raw-claims")]
+    pub(crate) fn claims(&self) -> serde_json::Map<String, serde_json::Value> {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(claims)) => claims,
+            _ => serde_json::Map::new(),
+        }
+    }
 }
 
 impl From<UserInfoResponse> for WartIDSession {
     fn from(info: UserInfoResponse) -> Self {
+        let now = Utc::now().timestamp();
+        #[cfg(feature = "raw-claims")]
+        let raw_claims = info.claims();
+
         Self {
             id: info.sub,
             name: info.name,
+            preferred_username: info.preferred_username,
+            picture: info.picture,
             email: info.email,
-            scopes: "".into(), // TODO
+            email_verified: info.email_verified.unwrap_or(false),
+            scopes: String::new(),
+            requested_scopes: String::new(),
+            issued_at: now,
+            last_seen: now,
+            userinfo_fetched_at: now,
+            token_expires_at: None,
+            remember_me: false,
+            acr: None,
+            amr: Vec::new(),
+            id_token: None,
+            extra: info.extra,
+            #[cfg(feature = "raw-claims")]
+            raw_claims,
         }
     }
 }
 
+impl WartIDSession {
+    /// Replaces the identity of the session with fresh userinfo, returns `false` without updating
+    /// anything if the userinfo is about someone else
+    pub(crate) fn update_userinfo(&mut self, info: UserInfoResponse, now: i64) -> bool {
+        if info.sub != self.id {
+            return false;
+        }
+
+        // ID token claims are kept, userinfo ones are replaced
+        #[cfg(feature = "raw-claims")]
+        self.raw_claims.extend(info.claims());
+        self.name = info.name;
+        self.preferred_username = info.preferred_username;
+        self.picture = info.picture;
+        self.email = info.email;
+        self.email_verified = info.email_verified.unwrap_or(false);
+        self.extra = info.extra;
+        self.userinfo_fetched_at = now;
+
+        true
+    }
+}
+
+/// Access and refresh tokens of a session, refreshed when they expire
 pub enum Authorization<'a> {
     Clean {
         access_token: &'a str,
-        refresh_token: &'a str,
+        /// `None` if WartID didn't issue a refresh token, in which case the access token can't be
+        /// refreshed
+        refresh_token: Option<&'a str>,
+        expires_at: Option<i64>,
     },
-    /// Set when the tokens were refreshed. They should be written back to cookies before dropping,
-    /// or the session will be invalid starting from the next request.
+    /// Set when the tokens were refreshed. They must be [taken][Authorization::take_updated] and
+    /// written back to the session before dropping, or the session will be invalid starting from
+    /// the next request. Debug builds panic if they aren't.
     Dirty {
         access_token: String,
         refresh_token: String,
+        expires_at: Option<i64>,
     },
 }
 
 impl<'a> Authorization<'a> {
-    pub fn new(access_token: &'a str, refresh_token: &'a str) -> Self {
+    pub fn new(access_token: &'a str, refresh_token: Option<&'a str>) -> Self {
         Self::Clean {
             access_token,
             refresh_token,
+            expires_at: None,
         }
     }
+
+    /// Sets the expiry timestamp of the access token, as stored when it was issued
+    pub fn with_expiry(mut self, timestamp: Option<i64>) -> Self {
+        match &mut self {
+            Self::Clean { expires_at, .. } | Self::Dirty { expires_at, .. } => {
+                *expires_at = timestamp
+            }
+        }
+        self
+    }
 }
 
 impl Authorization<'_> {
@@ -87,74 +464,283 @@ impl Authorization<'_> {
         }
     }
 
-    fn refresh_token(&self) -> &str {
+    fn refresh_token(&self) -> Option<&str> {
         match self {
             Self::Clean { refresh_token, .. } => *refresh_token,
-            Self::Dirty { refresh_token, .. } => &*refresh_token,
+            Self::Dirty { refresh_token, .. } => Some(&*refresh_token),
         }
     }
 
-    pub fn expired(&self) -> bool {
-        #[derive(serde::Deserialize)]
-        struct PartialClaims {
-            exp: u64,
-        }
+    /// Returns the refreshed access token, refresh token and access token expiry, if the tokens
+    /// were refreshed
+    #[must_use = "refreshed tokens must be written back to the session"]
+    pub fn take_updated(mut self) -> Option<(String, String, Option<i64>)> {
+        let updated = match &mut self {
+            Self::Dirty {
+                access_token,
+                refresh_token,
+                expires_at,
+            } => Some((
+                std::mem::take(access_token),
+                std::mem::take(refresh_token),
+                *expires_at,
+            )),
+            Self::Clean { .. } => None,
+        };
 
-        match jsonwebtoken::dangerous_insecure_decode::<PartialClaims>(self.access_token()) {
-            Ok(claims) => {
-                let expiration = Utc.timestamp(claims.claims.exp as _, 0);
+        // Taken, so nothing is lost
+        std::mem::forget(self);
+        updated
+    }
 
-                expiration < Utc::now()
-            }
-            Err(err) => {
-                log::error!("[Authorization::expired] {}", err);
-                true
-            }
+    fn expires_at(&self) -> Option<i64> {
+        match self {
+            Self::Clean { expires_at, .. } | Self::Dirty { expires_at, .. } => *expires_at,
         }
     }
 
+    /// Whether it's time to refresh the access token, i.e. it expires within the client's leeway
+    ///
+    /// The expiry stored when the token was issued is used if known, for both JWTs and opaque
+    /// tokens. Otherwise JWTs are decoded, those that can't be are considered expired. Opaque
+    /// tokens without a known expiry are never considered expired, as refreshing them on every
+    /// request wouldn't help.
+    pub fn expired(&self, client: &Client) -> bool {
+        let expires_at = match self.expires_at() {
+            Some(expires_at) => expires_at,
+            None if client.opaque_tokens => return false,
+            None => match jwt::expiry(self.access_token()) {
+                Some(expires_at) => expires_at,
+                None => {
+                    log::error!("[Authorization::expired] can't decode the access token");
+                    return true;
+                }
+            },
+        };
+
+        let leeway = chrono::Duration::from_std(client.leeway).unwrap_or_else(|_| {
+            log::error!("[Authorization::expired] leeway out of range");
+            chrono::Duration::zero()
+        });
+
+        Utc.timestamp(expires_at, 0) < Utc::now() + leeway
+    }
+
+    /// Refreshes the tokens if the access token [expired][Authorization::expired], the
+    /// authorization then becoming [Dirty][Authorization::Dirty]
     pub async fn try_refresh(
         &mut self,
         context: &WIDContext,
         client: &Client,
-    ) -> Result<(), reqwest::Error> {
-        if self.expired() {
+    ) -> Result<(), WartIDError> {
+        self.try_refresh_with(context, client, client).await
+    }
+
+    /// Same as [try_refresh][Authorization::try_refresh], refreshing the tokens with the given
+    /// [TokenRefresher], e.g. a fake one in tests
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self, context, client, refresher))
+    )]
+    pub async fn try_refresh_with(
+        &mut self,
+        context: &WIDContext,
+        client: &Client,
+        refresher: &dyn TokenRefresher,
+    ) -> Result<(), WartIDError> {
+        if self.expired(client) {
+            let refresh_token = match self.refresh_token() {
+                Some(refresh_token) => refresh_token.to_string(),
+                // Used as is, WartID will tell if it's still accepted
+                None => {
+                    log::debug!("[Authorization::try_refresh] no refresh token, can't refresh");
+                    return Ok(());
+                }
+            };
+
             log::debug!(
                 "[Authorization::try_refresh] refreshing {}",
                 self.access_token()
             );
 
-            let token = client
-                .request_token_refresh(context, self.refresh_token())
-                .await?;
-
-            *self = Self::Dirty {
-                access_token: token.access_token,
-                refresh_token: token
-                    .refresh_token
-                    .unwrap_or_else(|| self.refresh_token().to_string()),
+            let token = match refresher.refresh(context, &refresh_token).await {
+                Ok(token) => token,
+                Err(err) => {
+                    context.metrics.refresh_failed();
+                    return Err(err);
+                }
             };
+            context.metrics.refreshed();
+
+            let previous = std::mem::replace(
+                self,
+                Self::Dirty {
+                    expires_at: token.expires_at(),
+                    access_token: token.access_token,
+                    refresh_token: token.refresh_token.unwrap_or(refresh_token),
+                },
+            );
+            // Superseded by the new tokens
+            let _ = previous.take_updated();
         }
 
         Ok(())
     }
 
-    async fn bearer(
-        &mut self,
-        context: &WIDContext,
-        client: &Client,
-    ) -> Result<&str, reqwest::Error> {
+    async fn bearer(&mut self, context: &WIDContext, client: &Client) -> Result<&str, WartIDError> {
         self.try_refresh(context, client).await?;
         Ok(self.access_token())
     }
 }
 
+/// Refresh token grant, abstracted so refreshes can be tested without WartID
+#[async_trait::async_trait]
+pub trait TokenRefresher: Send + Sync {
+    async fn refresh(
+        &self,
+        context: &WIDContext,
+        refresh_token: &str,
+    ) -> Result<TokenResponse, WartIDError>;
+}
+
+#[async_trait::async_trait]
+impl TokenRefresher for Client {
+    async fn refresh(
+        &self,
+        context: &WIDContext,
+        refresh_token: &str,
+    ) -> Result<TokenResponse, WartIDError> {
+        self.request_token_refresh(context, refresh_token).await
+    }
+}
+
+impl Drop for Authorization<'_> {
+    fn drop(&mut self) {
+        debug_assert!(
+            std::thread::panicking() || matches!(self, Self::Clean { .. }),
+            "refreshed tokens dropped without being taken, the session is lost",
+        );
+    }
+}
+
+/// Timeout of [Client::probe] when the client doesn't set one
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl Client {
+    /// Checks WartID can be reached, for readiness probes
+    ///
+    /// Requests the JWKS endpoint if set, or else the OpenID discovery document of the WartID
+    /// instance, so DNS, TLS or connectivity problems show up at deploy time rather than on the
+    /// first login.
+    pub async fn probe(&self) -> Result<(), WartIDError> {
+        let url = match &self.url_jwks {
+            Some(url) => url.clone(),
+            None => self
+                .url_authorize
+                .join("/.well-known/openid-configuration")
+                .expect("the discovery URL is always valid"),
+        };
+
+        let request = self
+            .client
+            .get(url)
+            .timeout(self.timeout.unwrap_or(PROBE_TIMEOUT));
+
+        request.send().await?.error_for_status()?;
+
+        Ok(())
+    }
+
+    /// WartID's signing keys, fails with [InvalidToken][WartIDError::InvalidToken] if no JWKS
+    /// endpoint is set
+    ///
+    /// The keys are cached for the [JWKS TTL][ClientBuilder::jwks_ttl], and fetched again sooner
+    /// if `kid` isn't one of them, as WartID may have rotated its keys.
+    pub(crate) async fn request_jwks(
+        &self,
+        kid: Option<&str>,
+    ) -> Result<Arc<jwt::Jwks>, WartIDError> {
+        let url = match &self.url_jwks {
+            Some(url) => url.clone(),
+            None => {
+                log::error!("[Client::request_jwks] no JWKS endpoint set");
+                return Err(WartIDError::InvalidToken);
+            }
+        };
+
+        // Held during the fetch, so concurrent verifications wait for a single request
+        let mut cache = self.jwks_cache.lock().await;
+
+        if let Some((fetched_at, jwks)) = &*cache {
+            let age = fetched_at.elapsed();
+            let unknown_kid = kid.map_or(false, |kid| !jwks.contains(kid));
+
+            if age < self.jwks_ttl && !(unknown_kid && age >= JWKS_MIN_REFRESH) {
+                return Ok(jwks.clone());
+            }
+        }
+
+        let response = self.send(self.client.get(url)).await?;
+        let jwks = Arc::new(response.error_for_status()?.json::<jwt::Jwks>().await?);
+        *cache = Some((Instant::now(), jwks.clone()));
+
+        Ok(jwks)
+    }
+
+    /// Asks WartID's introspection endpoint about an access token, fails with
+    /// [Unauthorized][WartIDError::Unauthorized] if it's no longer active, or
+    /// [InvalidToken][WartIDError::InvalidToken] if no introspection endpoint is set
+    pub(crate) async fn introspect(
+        &self,
+        context: &WIDContext,
+        token: &str,
+    ) -> Result<jwt::AccessTokenClaims, WartIDError> {
+        #[derive(serde::Serialize)]
+        struct IntrospectionRequest<'a> {
+            token: &'a str,
+            token_type_hint: &'static str,
+            client_id: &'a str,
+            client_secret: &'a str,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct IntrospectionResponse {
+            active: bool,
+            #[serde(flatten)]
+            claims: jwt::AccessTokenClaims,
+        }
+
+        let url = match &self.url_introspection {
+            Some(url) => url.clone(),
+            None => {
+                log::error!("[Client::introspect] no introspection endpoint set");
+                return Err(WartIDError::InvalidToken);
+            }
+        };
+
+        let data = IntrospectionRequest {
+            token,
+            token_type_hint: "access_token",
+            client_id: &context.credentials.client_id,
+            client_secret: context.credentials.client_secret.expose(),
+        };
+        let response = self.send(self.client.post(url).form(&data)).await?;
+        let introspection: IntrospectionResponse = response.error_for_status()?.json().await?;
+
+        if introspection.active {
+            Ok(introspection.claims)
+        } else {
+            Err(WartIDError::Unauthorized {
+                www_authenticate: None,
+            })
+        }
+    }
+
     pub async fn request_token(
         &self,
         context: &WIDContext,
         authorization_code: &str,
-    ) -> Result<TokenResponse, reqwest::Error> {
+    ) -> Result<TokenResponse, WartIDError> {
         let data = TokenRequestData {
             grant_type: "authorization_code",
             code: Some(authorization_code),
@@ -163,59 +749,146 @@ impl Client {
             scope: None,
 
             client_id: &context.credentials.client_id,
-            client_secret: &context.credentials.client_secret,
+            client_secret: context.credentials.client_secret.expose(),
         };
 
-        let response = self
-            .client
-            .post(self.url_token.clone())
-            .form(&data)
-            .send()
-            .await?;
-
-        Ok(response.json().await?)
+        self.post_token(&data).await
     }
 
     pub async fn request_token_refresh(
         &self,
         context: &WIDContext,
         refresh_token: &str,
-    ) -> Result<TokenResponse, reqwest::Error> {
+    ) -> Result<TokenResponse, WartIDError> {
+        self.refresh_grant(context, refresh_token, None).await
+    }
+
+    /// Refreshes the tokens, requesting an access token limited to the given scopes
+    ///
+    /// The scopes must have been granted originally, WartID can only narrow them. The session
+    /// isn't affected, so this is meant for least-privilege tokens given to background tasks.
+    pub async fn request_token_refresh_with_scope<I: IntoIterator<Item = Scope>>(
+        &self,
+        context: &WIDContext,
+        refresh_token: &str,
+        scopes: I,
+    ) -> Result<TokenResponse, WartIDError> {
+        let scope = Scope::join(scopes);
+        self.refresh_grant(context, refresh_token, Some(&scope))
+            .await
+    }
+
+    async fn refresh_grant(
+        &self,
+        context: &WIDContext,
+        refresh_token: &str,
+        scope: Option<&str>,
+    ) -> Result<TokenResponse, WartIDError> {
         let data = TokenRequestData {
             grant_type: "refresh_token",
             code: None,
             refresh_token: Some(refresh_token),
             redirect_uri: "",
-            scope: None,
+            scope,
 
             client_id: &context.credentials.client_id,
-            client_secret: &context.credentials.client_secret,
+            client_secret: context.credentials.client_secret.expose(),
         };
 
+        self.post_token(&data).await
+    }
+
+    /// Sends a request to the token endpoint, telling rejected grants apart from other errors
+    async fn post_token(&self, data: &TokenRequestData<'_>) -> Result<TokenResponse, WartIDError> {
         let response = self
-            .client
-            .post(self.url_token.clone())
-            .form(&data)
-            .send()
+            .send(self.client.post(self.url_token.clone()).form(data))
             .await?;
 
-        Ok(response.json().await?)
+        if response.status() == reqwest::StatusCode::BAD_REQUEST {
+            let error: OAuthErrorResponse = response.json().await?;
+            return Err(match error.error.as_str() {
+                "invalid_grant" => WartIDError::InvalidGrant,
+                _ => WartIDError::TokenEndpoint(error.error),
+            });
+        }
+
+        let token: TokenResponse = response.error_for_status()?.json().await?;
+        token.check_token_type()
     }
 
+    /// Requests the user's identity, refreshing the tokens first if the access token expired
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self, context, authorization), fields(url = %self.url_userinfo))
+    )]
     pub async fn request_userinfo<'a>(
         &self,
         context: &WIDContext,
         authorization: &mut Authorization<'a>,
-    ) -> Result<UserInfoResponse, reqwest::Error> {
-        let response = self
+    ) -> Result<UserInfoResponse, WartIDError> {
+        let access_token = authorization.bearer(context, self).await?;
+        self.request_userinfo_with_token(access_token).await
+    }
+
+    /// Same as [request_userinfo][Client::request_userinfo], answered from the
+    /// [cache][ClientBuilder::userinfo_cache] when it has a fresh copy for the subject `sub`
+    pub async fn userinfo_cached<'a>(
+        &self,
+        context: &WIDContext,
+        authorization: &mut Authorization<'a>,
+        sub: &str,
+    ) -> Result<UserInfoResponse, WartIDError> {
+        let cache = match &self.userinfo_cache {
+            Some(cache) => cache,
+            None => return self.request_userinfo(context, authorization).await,
+        };
+
+        if let Some(userinfo) = cache.get(sub) {
+            return Ok(userinfo);
+        }
+
+        let userinfo = self.request_userinfo(context, authorization).await?;
+        cache.insert(userinfo.clone());
+
+        Ok(userinfo)
+    }
+
+    /// Drops the cached userinfo of the subject, if any, e.g. once they logged out
+    pub fn invalidate_userinfo(&self, sub: &str) {
+        if let Some(cache) = &self.userinfo_cache {
+            cache.remove(sub);
+        }
+    }
+
+    pub(crate) fn caches_userinfo(&self) -> bool {
+        self.userinfo_cache.is_some()
+    }
+
+    /// Requests the user's identity with an access token known to be valid, without checking its
+    /// expiry nor refreshing it
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self, access_token), fields(url = %self.url_userinfo))
+    )]
+    pub async fn request_userinfo_with_token(
+        &self,
+        access_token: &str,
+    ) -> Result<UserInfoResponse, WartIDError> {
+        let request = self
             .client
             .get(self.url_userinfo.clone())
-            .header(
-                "Authorization",
-                &format!("Bearer {}", authorization.bearer(context, self).await?),
-            )
-            .send()
-            .await?;
+            .header("Authorization", &format!("Bearer {}", access_token));
+        let response = self.send(request).await?;
+
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            let www_authenticate = response
+                .headers()
+                .get(reqwest::header::WWW_AUTHENTICATE)
+                .and_then(|header| header.to_str().ok())
+                .map(String::from);
+
+            return Err(WartIDError::Unauthorized { www_authenticate });
+        }
 
         Ok(response.json().await?)
     }
@@ -0,0 +1,292 @@
+use super::{Client, UserinfoCache, JWKS_TTL};
+use crate::{ConfigError, WIDContextEndpoints, WartIDError};
+use reqwest::Url;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Error returned by [ClientBuilder::build]
+#[derive(Debug)]
+pub enum ClientBuildError {
+    /// A required endpoint wasn't set
+    MissingEndpoint(&'static str),
+    /// An endpoint isn't a valid URL
+    InvalidUrl {
+        endpoint: &'static str,
+        error: url::ParseError,
+    },
+    /// The HTTP client couldn't be built
+    Http(reqwest::Error),
+}
+
+impl fmt::Display for ClientBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingEndpoint(endpoint) => write!(f, "missing {} endpoint", endpoint),
+            Self::InvalidUrl { endpoint, error } => {
+                write!(f, "invalid {} endpoint URL: {}", endpoint, error)
+            }
+            Self::Http(err) => write!(f, "can't build the HTTP client: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ClientBuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::MissingEndpoint(_) => None,
+            Self::InvalidUrl { error, .. } => Some(error),
+            Self::Http(err) => Some(err),
+        }
+    }
+}
+
+/// OpenID provider metadata, see [ClientBuilder::discover]
+#[derive(serde::Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+    jwks_uri: Option<String>,
+    introspection_endpoint: Option<String>,
+    revocation_endpoint: Option<String>,
+}
+
+/// Builder for a [Client], see [Client::builder]
+///
+/// The authorize, token and userinfo endpoints are required, the others are optional.
+#[derive(Clone, Debug, Default)]
+pub struct ClientBuilder {
+    issuer: Option<String>,
+    authorize: Option<String>,
+    token: Option<String>,
+    userinfo: Option<String>,
+    jwks: Option<String>,
+    introspection: Option<String>,
+    revocation: Option<String>,
+
+    http_client: Option<reqwest::Client>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxies: Vec<reqwest::Proxy>,
+    retries: u32,
+    leeway: Duration,
+    opaque_tokens: bool,
+    jwks_ttl: Option<Duration>,
+    userinfo_cache: Option<(usize, Duration)>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the authorize, token and userinfo endpoints at once
+    pub fn endpoints(self, endpoints: WIDContextEndpoints) -> Self {
+        self.authorize(endpoints.authorize)
+            .token(endpoints.token)
+            .userinfo(endpoints.userinfo)
+    }
+
+    pub fn authorize(mut self, url: impl Into<String>) -> Self {
+        self.authorize = Some(url.into());
+        self
+    }
+
+    pub fn token(mut self, url: impl Into<String>) -> Self {
+        self.token = Some(url.into());
+        self
+    }
+
+    pub fn userinfo(mut self, url: impl Into<String>) -> Self {
+        self.userinfo = Some(url.into());
+        self
+    }
+
+    /// Sets the endpoints listed in the OpenID discovery document of the given issuer, e.g.
+    /// `https://id.wp-corp.eu.org`, keeping the others
+    ///
+    /// The document is requested with the [HTTP client][ClientBuilder::http_client], or else the
+    /// [connect timeout][ClientBuilder::connect_timeout] and [proxies][ClientBuilder::proxy], and
+    /// the [timeout][ClientBuilder::timeout] set so far.
+    pub async fn discover(mut self, issuer: &str) -> Result<Self, ConfigError> {
+        let issuer = issuer.trim_end_matches('/');
+
+        let http_client = self
+            .build_http_client()
+            .map_err(ConfigError::InvalidClient)?;
+        let mut request = http_client.get(format!("{}/.well-known/openid-configuration", issuer));
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+
+        let metadata = match request
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+        {
+            Ok(response) => response.json::<ProviderMetadata>().await,
+            Err(err) => Err(err),
+        }
+        .map_err(|err| ConfigError::Discovery(WartIDError::from(err)))?;
+
+        // Another issuer could impersonate WartID, see OpenID Connect Discovery 4.3
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(ConfigError::IssuerMismatch(metadata.issuer));
+        }
+
+        self.issuer = Some(metadata.issuer);
+        self.authorize = Some(metadata.authorization_endpoint);
+        self.token = Some(metadata.token_endpoint);
+        self.userinfo = metadata.userinfo_endpoint.or(self.userinfo);
+        self.jwks = metadata.jwks_uri.or(self.jwks);
+        self.introspection = metadata.introspection_endpoint.or(self.introspection);
+        self.revocation = metadata.revocation_endpoint.or(self.revocation);
+
+        Ok(self)
+    }
+
+    /// Issuer identifier of WartID, which the `iss` claim of ID tokens must match, set by
+    /// [discover][ClientBuilder::discover]. ID tokens' issuer isn't checked without it.
+    pub fn issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    pub fn jwks(mut self, url: impl Into<String>) -> Self {
+        self.jwks = Some(url.into());
+        self
+    }
+
+    pub fn introspection(mut self, url: impl Into<String>) -> Self {
+        self.introspection = Some(url.into());
+        self
+    }
+
+    pub fn revocation(mut self, url: impl Into<String>) -> Self {
+        self.revocation = Some(url.into());
+        self
+    }
+
+    /// HTTP client used for requests to WartID
+    ///
+    /// The [connect timeout][ClientBuilder::connect_timeout] and [proxies][ClientBuilder::proxy]
+    /// are ignored when a client is given, set them on the client itself instead.
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Timeout of each request to WartID, from connecting to reading the whole response
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Routes requests to WartID through the given proxy
+    ///
+    /// Without explicit proxies, the standard `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`
+    /// environment variables are honored.
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Number of times a request is retried after failing to connect or timing out, none by
+    /// default
+    ///
+    /// Requests rate limited by WartID (`429 Too Many Requests`) are retried too, after the
+    /// `Retry-After` delay it asks for, unless it exceeds 5 seconds.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// See [Client::with_leeway]
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// See [Client::with_opaque_tokens]
+    pub fn opaque_tokens(mut self) -> Self {
+        self.opaque_tokens = true;
+        self
+    }
+
+    /// How long WartID's signing keys are cached, one hour by default. Tokens signed with a key
+    /// missing from the cache still trigger a new fetch, so key rotations are picked up.
+    pub fn jwks_ttl(mut self, ttl: Duration) -> Self {
+        self.jwks_ttl = Some(ttl);
+        self
+    }
+
+    /// Caches the userinfo of up to `capacity` users for `ttl`, evicting the least recently used
+    /// ones, for [Client::userinfo_cached]. Disabled by default.
+    ///
+    /// Cached userinfo isn't requested with the user's current access token, so a short TTL is
+    /// advised: WartID can't tell a revoked session until the entry expires.
+    pub fn userinfo_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.userinfo_cache = Some((capacity, ttl));
+        self
+    }
+
+    /// The [HTTP client][ClientBuilder::http_client], or else one with the connect timeout and
+    /// proxies set so far
+    fn build_http_client(&self) -> Result<reqwest::Client, ClientBuildError> {
+        if let Some(client) = &self.http_client {
+            return Ok(client.clone());
+        }
+
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        for proxy in &self.proxies {
+            builder = builder.proxy(proxy.clone());
+        }
+        builder.build().map_err(ClientBuildError::Http)
+    }
+
+    pub fn build(self) -> Result<Client, ClientBuildError> {
+        fn parse(endpoint: &'static str, url: &str) -> Result<Url, ClientBuildError> {
+            Url::parse(url).map_err(|error| ClientBuildError::InvalidUrl { endpoint, error })
+        }
+
+        let required = |endpoint: &'static str, url: Option<String>| match url {
+            Some(url) => parse(endpoint, &url),
+            None => Err(ClientBuildError::MissingEndpoint(endpoint)),
+        };
+        let optional = |endpoint: &'static str, url: Option<String>| {
+            url.map(|url| parse(endpoint, &url)).transpose()
+        };
+
+        let client = self.build_http_client()?;
+
+        Ok(Client {
+            issuer: self.issuer,
+            url_authorize: required("authorize", self.authorize)?,
+            url_token: required("token", self.token)?,
+            url_userinfo: required("userinfo", self.userinfo)?,
+            url_jwks: optional("jwks", self.jwks)?,
+            url_introspection: optional("introspection", self.introspection)?,
+            url_revocation: optional("revocation", self.revocation)?,
+            client,
+            timeout: self.timeout,
+            retries: self.retries,
+            leeway: self.leeway,
+            opaque_tokens: self.opaque_tokens,
+            jwks_ttl: self.jwks_ttl.unwrap_or(JWKS_TTL),
+            jwks_cache: Arc::default(),
+            userinfo_cache: self
+                .userinfo_cache
+                .map(|(capacity, ttl)| Arc::new(UserinfoCache::new(capacity, ttl))),
+        })
+    }
+}
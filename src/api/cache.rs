@@ -0,0 +1,90 @@
+use super::UserInfoResponse;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Userinfo responses by subject, evicting the least recently used ones, see
+/// [ClientBuilder::userinfo_cache][super::ClientBuilder::userinfo_cache]
+#[derive(Debug)]
+pub(crate) struct UserinfoCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    by_sub: HashMap<String, (Instant, UserInfoResponse)>,
+    /// Subjects from the least to the most recently used
+    recency: VecDeque<String>,
+}
+
+impl Entries {
+    fn touch(&mut self, sub: &str) {
+        if let Some(position) = self.recency.iter().position(|used| used == sub) {
+            let used = self.recency.remove(position).unwrap();
+            self.recency.push_back(used);
+        }
+    }
+
+    fn remove(&mut self, sub: &str) {
+        self.by_sub.remove(sub);
+        self.recency.retain(|used| used != sub);
+    }
+}
+
+impl UserinfoCache {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Userinfo of the subject, if fetched less than the TTL ago
+    pub(crate) fn get(&self, sub: &str) -> Option<UserInfoResponse> {
+        let mut entries = self.lock();
+
+        let fresh = match entries.by_sub.get(sub) {
+            Some((fetched_at, info)) if fetched_at.elapsed() < self.ttl => Some(info.clone()),
+            Some(_) => None,
+            None => return None,
+        };
+
+        match fresh {
+            Some(_) => entries.touch(sub),
+            None => entries.remove(sub),
+        }
+
+        fresh
+    }
+
+    pub(crate) fn insert(&self, info: UserInfoResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.lock();
+        let sub = info.sub.clone();
+
+        entries.remove(&sub);
+        while entries.recency.len() >= self.capacity {
+            if let Some(oldest) = entries.recency.pop_front() {
+                entries.by_sub.remove(&oldest);
+            }
+        }
+
+        entries.recency.push_back(sub.clone());
+        entries.by_sub.insert(sub, (Instant::now(), info));
+    }
+
+    pub(crate) fn remove(&self, sub: &str) {
+        self.lock().remove(sub);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        // The entries are consistent between statements, a panic elsewhere can't corrupt them
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,80 @@
+//! Sessions of requests authenticated with a bearer access token rather than cookies
+
+use crate::{jwt, WIDContext, WartIDError, WartIDSession, WartIDSessionError};
+use chrono::Utc;
+
+/// Token of an `Authorization: Bearer` header value
+pub(crate) fn bearer_token(authorization: Option<&str>) -> Option<&str> {
+    let (scheme, token) = authorization?.split_once(' ')?;
+    let token = token.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+/// Builds the session of an access token, checked to be issued for this app by WartID's
+/// introspection endpoint if set, or else by verifying it against WartID's JWKS, so tokens issued
+/// to other clients of WartID are rejected
+///
+/// JWTs are checked for expiry first, sparing a request for tokens known to be expired. Opaque
+/// tokens are only accepted with an introspection endpoint. The session is granted the scopes of
+/// the token, and only lives as long as the request, it's never stored.
+pub(crate) async fn authenticate_bearer(
+    context: &WIDContext,
+    token: &str,
+) -> Result<WartIDSession, WartIDSessionError> {
+    let expires_at = jwt::expiry(token);
+    if let Some(expires_at) = expires_at {
+        if expires_at < Utc::now().timestamp() {
+            return Err(WartIDSessionError::Expired);
+        }
+    }
+
+    let client = &context.client;
+    let claims = if client.introspection_url().is_some() {
+        client.introspect(context, token).await
+    } else {
+        jwt::verify_access_token(client, token).await
+    };
+    let claims = match claims {
+        Ok(claims) => claims,
+        Err(err) => return Err(session_error(err)),
+    };
+
+    if !claims.issued_to(&context.credentials.client_id) {
+        log::error!("[authenticate_bearer] token issued for another client");
+        return Err(WartIDSessionError::InvalidBearer);
+    }
+
+    match client.request_userinfo_with_token(token).await {
+        Ok(userinfo) => {
+            let mut session: WartIDSession = userinfo.into();
+            session.token_expires_at = claims.exp.or(expires_at);
+            session.scopes = claims.scope.unwrap_or_default();
+            Ok(session)
+        }
+        Err(WartIDError::Unauthorized { .. }) => Err(WartIDSessionError::Revoked),
+        Err(WartIDError::Request(_)) | Err(WartIDError::RateLimited { .. }) => {
+            Err(WartIDSessionError::Network)
+        }
+        Err(err) => {
+            log::error!("[authenticate_bearer] {}", err);
+            Err(WartIDSessionError::MissingUserinfo)
+        }
+    }
+}
+
+/// Session error of a failed token check
+fn session_error(err: WartIDError) -> WartIDSessionError {
+    match err {
+        WartIDError::Unauthorized { .. } => WartIDSessionError::Revoked,
+        WartIDError::Request(_) | WartIDError::RateLimited { .. } => WartIDSessionError::Network,
+        err => {
+            log::error!("[authenticate_bearer] {}", err);
+            WartIDSessionError::InvalidBearer
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use crate::ClientBuildError;
+use std::fmt;
+
+/// Errors of the authentication flow
+#[derive(Debug)]
+pub enum WartIDError {
+    /// The callback was reached without the expected `code` and `state` parameters
+    InvalidCallback,
+    /// The state cookie set at login is missing, usually because it expired
+    MissingState,
+    /// The state returned by WartID doesn't match the one set at login
+    StateMismatch,
+    /// A token returned by WartID failed verification
+    InvalidToken,
+    /// The user's email isn't verified, while the context
+    /// [requires it][crate::WIDContext::with_verified_email_required]
+    UnverifiedEmail,
+    /// The context's [user authorizer][crate::WIDContext::with_user_authorizer] refused the user
+    AccessDenied,
+    /// WartID rejected the access token, `www_authenticate` holds the reason it gave if any
+    Unauthorized { www_authenticate: Option<String> },
+    /// WartID rejected the authorization code, usually because it was already used, or the
+    /// refresh token, usually because it expired or was revoked
+    InvalidGrant,
+    /// The token endpoint returned another OAuth error, e.g. `invalid_client` when the
+    /// credentials are wrong
+    TokenEndpoint(String),
+    /// A request to WartID failed
+    Request(reqwest::Error),
+    /// WartID is rate limiting the client, `retry_after` holds the delay it asked for if any
+    RateLimited {
+        retry_after: Option<std::time::Duration>,
+    },
+    /// A response or token claims from WartID couldn't be decoded
+    Decoding(serde_json::Error),
+    /// WartID issued an access token of another type than `Bearer`, which the crate can't send
+    UnsupportedTokenType(String),
+    /// The context's [login hook][crate::WIDContext::with_login_hook] failed
+    LoginHook(crate::hooks::HookError),
+}
+
+impl WartIDError {
+    /// Short identifier of the error, passed to the
+    /// [callback error page][crate::WIDContext::with_callback_error_redirect]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidCallback => "invalid_callback",
+            Self::MissingState => "missing_state",
+            Self::StateMismatch => "state_mismatch",
+            Self::InvalidToken => "invalid_token",
+            Self::UnverifiedEmail => "unverified_email",
+            Self::AccessDenied => "access_denied",
+            Self::Unauthorized { .. } => "unauthorized",
+            Self::InvalidGrant => "invalid_grant",
+            Self::TokenEndpoint(_) => "token_endpoint",
+            Self::Request(_) => "request",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::Decoding(_) => "decoding",
+            Self::UnsupportedTokenType(_) => "unsupported_token_type",
+            Self::LoginHook(_) => "login_hook",
+        }
+    }
+}
+
+impl fmt::Display for WartIDError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCallback => write!(f, "invalid callback parameters"),
+            Self::MissingState => write!(f, "missing state cookie"),
+            Self::StateMismatch => write!(f, "state mismatch"),
+            Self::InvalidToken => write!(f, "invalid token"),
+            Self::UnverifiedEmail => write!(f, "unverified email"),
+            Self::AccessDenied => write!(f, "access denied"),
+            Self::Unauthorized {
+                www_authenticate: Some(www_authenticate),
+            } => write!(f, "access token rejected: {}", www_authenticate),
+            Self::Unauthorized {
+                www_authenticate: None,
+            } => write!(f, "access token rejected"),
+            Self::InvalidGrant => write!(f, "authorization code rejected"),
+            Self::TokenEndpoint(error) => write!(f, "token endpoint error: {}", error),
+            Self::Request(err) => write!(f, "request to WartID failed: {}", err),
+            Self::RateLimited {
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "rate limited by WartID, retry after {}s",
+                retry_after.as_secs()
+            ),
+            Self::RateLimited { retry_after: None } => write!(f, "rate limited by WartID"),
+            Self::Decoding(err) => write!(f, "can't decode WartID's response: {}", err),
+            Self::UnsupportedTokenType(token_type) => {
+                write!(f, "unsupported access token type {:?}", token_type)
+            }
+            Self::LoginHook(err) => write!(f, "login hook failed: {}", err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for WartIDError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<serde_json::Error> for WartIDError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Decoding(err)
+    }
+}
+
+/// Token errors are logged, their details are meaningless to users
+impl From<jsonwebtoken::errors::Error> for WartIDError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        log::error!("[WartIDError::from] {}", err);
+        Self::InvalidToken
+    }
+}
+
+impl std::error::Error for WartIDError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Request(err) => Some(err),
+            Self::Decoding(err) => Some(err),
+            Self::LoginHook(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Reason a session has no email, see
+/// [WartIDSession::email_or_err][crate::WartIDSession::email_or_err]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EmailError {
+    /// The `email` scope wasn't requested, or the user refused to share their email
+    NotGranted,
+    /// The `email` scope was granted, but WartID returned no email
+    NotProvided,
+}
+
+impl fmt::Display for EmailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotGranted => write!(f, "email scope not granted"),
+            Self::NotProvided => write!(f, "no email provided"),
+        }
+    }
+}
+
+impl std::error::Error for EmailError {}
+
+/// Misconfiguration of a [WIDContext][crate::WIDContext], see
+/// [WIDContext::validate][crate::WIDContext::validate]
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The callback URL isn't HTTPS, while the [HttpsPolicy][crate::HttpsPolicy] requires it
+    InsecureCallback(String),
+    /// The callback URL can't be parsed
+    InvalidCallback(String),
+    /// The identity is read from the ID token, but the client has no JWKS endpoint to verify it
+    MissingJwks,
+    /// The login, callback and logout URLs aren't the routes' paths under a common mount path,
+    /// see [WIDContextUrls::mount_path][crate::WIDContextUrls::mount_path]
+    UnmountableUrls,
+    /// A required environment variable isn't set, see
+    /// [WIDContext::from_env][crate::WIDContext::from_env]
+    MissingEnv(&'static str),
+    /// The OpenID discovery document couldn't be fetched
+    Discovery(WartIDError),
+    /// The OpenID discovery document is for another issuer than the requested one
+    IssuerMismatch(String),
+    /// The [Client][crate::Client] can't be built from the configured endpoints
+    InvalidClient(ClientBuildError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsecureCallback(url) => write!(f, "callback URL {} isn't HTTPS", url),
+            Self::InvalidCallback(url) => write!(f, "invalid callback URL {}", url),
+            Self::MissingJwks => write!(f, "no JWKS endpoint to verify ID tokens"),
+            Self::UnmountableUrls => write!(
+                f,
+                "the login, callback and logout URLs don't share a mount path"
+            ),
+            Self::MissingEnv(name) => write!(f, "no {} set", name),
+            Self::Discovery(err) => write!(f, "OpenID discovery failed: {}", err),
+            Self::IssuerMismatch(issuer) => {
+                write!(f, "discovery document for another issuer: {}", issuer)
+            }
+            Self::InvalidClient(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Discovery(err) => Some(err),
+            Self::InvalidClient(err) => Some(err),
+            _ => None,
+        }
+    }
+}
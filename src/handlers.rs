@@ -1,31 +1,149 @@
+use crate::Scope;
 use std::collections::HashSet;
 
 /// Route to initiate the authentication protocol
 #[derive(Clone)]
 pub struct Login {
-    /// A path to redirect to once the flow reaches its end
+    /// A path to redirect to once the flow reaches its end. If `None`, the `next` query parameter
+    /// of the login request is used if it's a same-origin path.
     pub(crate) redirect_to: Option<String>,
 
-    pub(crate) requested_scopes: HashSet<&'static str>,
+    pub(crate) requested_scopes: HashSet<Scope>,
+
+    /// Whether WartID should POST the authorization response to the callback instead of passing
+    /// it in the query string
+    pub(crate) form_post: bool,
+
+    /// Whether the session cookies should outlive the browser session
+    pub(crate) remember_me: bool,
+
+    /// Requested authentication context classes, space-separated, e.g. to require MFA
+    pub(crate) acr_values: Option<String>,
+
+    /// API the access token is requested for
+    pub(crate) audience: Option<String>,
+
+    /// Values of the `prompt` parameter, e.g. `select_account`
+    pub(crate) prompts: Vec<String>,
+
+    /// Provider-specific parameters appended to the authorization request
+    pub(crate) extra_params: Vec<(String, String)>,
+
+    /// Whether users already logged in skip WartID and are sent straight to their page
+    pub(crate) skip_if_logged_in: bool,
 }
 
 impl Login {
     /// Constructs the most basic scopes request
     pub fn basic() -> Self {
         let mut scopes = HashSet::new();
-        scopes.insert("basic");
+        scopes.insert(Scope::Basic);
         Self {
             redirect_to: None,
             requested_scopes: scopes,
+            form_post: false,
+            remember_me: false,
+            acr_values: None,
+            audience: None,
+            prompts: Vec::new(),
+            extra_params: Vec::new(),
+            skip_if_logged_in: false,
         }
     }
 
     /// Adds email requirement to the scopes
-    pub fn with_email(mut self) -> Self {
-        self.requested_scopes.insert("email");
+    pub fn with_email(self) -> Self {
+        self.with_scope(Scope::Email)
+    }
+
+    /// Requests `offline_access`, so WartID issues a refresh token and sessions outlive the
+    /// access token
+    pub fn offline(self) -> Self {
+        self.with_scope(Scope::OfflineAccess)
+    }
+
+    /// Adds the given scope to the requested ones
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.requested_scopes.insert(scope);
+        self
+    }
+
+    /// Requests the authorization response to be POSTed to the callback (`response_mode=form_post`)
+    ///
+    /// The callback route must accept `POST` requests, which [routes][crate::rocket::routes] takes
+    /// care of.
+    pub fn with_form_post(mut self) -> Self {
+        self.form_post = true;
+        self
+    }
+
+    /// Keeps the session across browser restarts, for as long as the context's
+    /// [cookie max-age][crate::WIDContext::with_cookie_max_age] or else 30 days, instead of using
+    /// session cookies
+    pub fn remember_me(mut self) -> Self {
+        self.remember_me = true;
+        self
+    }
+
+    /// Requests the given authentication context classes (`acr_values`), space-separated by order
+    /// of preference. WartID may not honor them, so sensitive routes should check them with
+    /// [WartIDSession::require_acr][crate::WartIDSession::require_acr].
+    pub fn with_acr_values(mut self, acr_values: &str) -> Self {
+        self.acr_values = Some(acr_values.to_string());
+        self
+    }
+
+    /// Requests an access token for the given API (`audience`), for providers issuing tokens
+    /// which resource servers can't verify otherwise
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    /// Asks WartID to let the user choose the account to log in with (`prompt=select_account`),
+    /// e.g. on shared devices, rather than using the one they're logged in with
+    pub fn force_account_selection(self) -> Self {
+        self.with_prompt("select_account")
+    }
+
+    /// Adds a value to the `prompt` parameter of the authorization request, e.g. `login` to make
+    /// the user authenticate again, or `consent`
+    ///
+    /// Values are combined, space-separated, with the ones of other calls, including
+    /// [extra][Login::with_extra_param] `prompt` parameters.
+    pub fn with_prompt(mut self, prompt: &str) -> Self {
+        if !self.prompts.iter().any(|known| known == prompt) {
+            self.prompts.push(prompt.to_string());
+        }
         self
     }
 
+    /// Appends a parameter to the authorization request, after the ones set by the crate, e.g. a
+    /// provider-specific `kc_idp_hint`
+    ///
+    /// Parameters the crate already sets (`client_id`, `state`, `scope`...) can't be overridden
+    /// and are ignored.
+    pub fn with_extra_param(mut self, key: &str, value: &str) -> Self {
+        self.extra_params.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sends users who already have a valid session straight to the page they would land on once
+    /// logged in, rather than through WartID again, e.g. when following a stale login link
+    ///
+    /// Only applies to the login route, not to `Login`s returned by other routes. Ignored when a
+    /// [prompt][Login::with_prompt] is requested, as WartID must then be reached.
+    pub fn skip_if_logged_in(mut self) -> Self {
+        self.skip_if_logged_in = true;
+        self
+    }
+
+    /// Whether a `prompt` is requested, through [with_prompt][Login::with_prompt] or an
+    /// [extra parameter][Login::with_extra_param]
+    pub(crate) fn has_prompt(&self) -> bool {
+        !self.prompts.is_empty() || self.extra_params.iter().any(|(key, _)| key == "prompt")
+    }
+
     /// Add or replaces the [redirection URL][Login::redirect_to] of the flow
     pub fn with_redirection(mut self, url: String) -> Self {
         self.redirect_to = Some(url);
@@ -39,6 +157,14 @@ pub struct Callback;
 
 /// Logout route
 ///
-/// Once logged out, redirects the user to [R][R] if some, or else "/"
+/// Once logged out, redirects the user to the given URL if some, or else to the `next` query
+/// parameter if it's a same-origin path, or else "/"
+///
+/// [routes][crate::rocket::routes] mounts it as a `POST` route, so a mere `<img src="/logout">`
+/// on another site can't log users out. Cross-site forms can still reach it though, so it should
+/// be called from a form carrying a CSRF token checked by the app. For backwards compatibility,
+/// [WIDContext::with_get_logout][crate::WIDContext::with_get_logout] makes it answer `GET`
+/// requests too, and responding with a `Logout` from a `GET` route still works, both being
+/// vulnerable to logout CSRF.
 #[derive(Clone)]
-pub struct Logout(pub Option<&'static str>);
+pub struct Logout(pub Option<String>);
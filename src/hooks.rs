@@ -0,0 +1,48 @@
+//! # Event hooks
+//!
+//! Extension points to react to the authentication flow, e.g. to provision a local user or log an
+//! audit event, set on the [WIDContext][crate::WIDContext].
+
+use crate::{TokenResponse, UserInfoResponse};
+
+pub type HookError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Called on each successful login, before the session is saved and the user is redirected
+///
+/// An error aborts the login: no session is saved, and the callback fails with
+/// [WartIDError::LoginHook][crate::WartIDError::LoginHook].
+#[async_trait::async_trait]
+pub trait LoginHook: Send + Sync {
+    async fn on_login(
+        &self,
+        token: &TokenResponse,
+        userinfo: &UserInfoResponse,
+    ) -> Result<(), HookError>;
+}
+
+/// Decides whether a user may log in at all, e.g. against an allow-list or their email's domain,
+/// before any session is set up
+///
+/// Closures taking the userinfo implement it:
+///
+/// ```
+/// # fn example(context: wartid_client::WIDContext) -> wartid_client::WIDContext {
+/// context.with_user_authorizer(|userinfo: &wartid_client::UserInfoResponse| {
+///     userinfo.email().map_or(false, |email| email.ends_with("@wp-corp.eu.org"))
+/// })
+/// # }
+/// ```
+#[async_trait::async_trait]
+pub trait UserAuthorizer: Send + Sync {
+    async fn authorize_user(&self, userinfo: &UserInfoResponse) -> bool;
+}
+
+#[async_trait::async_trait]
+impl<F> UserAuthorizer for F
+where
+    F: Fn(&UserInfoResponse) -> bool + Send + Sync,
+{
+    async fn authorize_user(&self, userinfo: &UserInfoResponse) -> bool {
+        self(userinfo)
+    }
+}
@@ -0,0 +1,339 @@
+//! Token verification
+
+use crate::{Client, UserInfoResponse, WartIDError};
+use jsonwebtoken::{Algorithm, DecodingKey, Header, Validation};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+/// Reads the claims of a token without verifying its signature nor its validity period
+fn insecure_decode<T: DeserializeOwned>(token: &str) -> jsonwebtoken::errors::Result<T> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+
+    jsonwebtoken::decode::<T>(token, &DecodingKey::from_secret(&[]), &validation)
+        .map(|data| data.claims)
+}
+
+#[derive(serde::Deserialize)]
+struct AtHashClaims {
+    at_hash: Option<String>,
+}
+
+/// Checks the `at_hash` claim of the ID token is the left half of the access token's hash, using
+/// the hash function of the ID token's signing algorithm
+///
+/// The claim is optional in the authorization code flow, so its absence isn't an error. The ID
+/// token must have been [verified][verify_id_token] first, as the check means nothing otherwise.
+pub(crate) fn verify_at_hash(id_token: &str, access_token: &str) -> Result<(), WartIDError> {
+    let header = jsonwebtoken::decode_header(id_token)?;
+    let claims = insecure_decode::<AtHashClaims>(id_token)?;
+
+    let at_hash = match claims.at_hash {
+        Some(at_hash) => at_hash,
+        None => return Ok(()),
+    };
+
+    let digest = match header.alg {
+        Algorithm::HS256 | Algorithm::RS256 | Algorithm::ES256 | Algorithm::PS256 => {
+            Sha256::digest(access_token.as_bytes()).to_vec()
+        }
+        Algorithm::HS384 | Algorithm::RS384 | Algorithm::ES384 | Algorithm::PS384 => {
+            Sha384::digest(access_token.as_bytes()).to_vec()
+        }
+        // Ed25519 signs with SHA-512
+        Algorithm::HS512 | Algorithm::RS512 | Algorithm::PS512 | Algorithm::EdDSA => {
+            Sha512::digest(access_token.as_bytes()).to_vec()
+        }
+    };
+
+    let expected = base64::encode_config(&digest[..digest.len() / 2], base64::URL_SAFE_NO_PAD);
+
+    if expected == at_hash {
+        Ok(())
+    } else {
+        log::error!("[verify_at_hash] at_hash doesn't match the access token");
+        Err(WartIDError::InvalidToken)
+    }
+}
+
+/// Reads the `sub` claim of a token without verifying it, returns `None` if the token isn't a JWT
+/// or has no subject
+pub(crate) fn subject(token: &str) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct SubjectClaims {
+        sub: Option<String>,
+    }
+
+    insecure_decode::<SubjectClaims>(token).ok()?.sub
+}
+
+/// Reads the `exp` claim of a token without verifying it, returns `None` if the token isn't a JWT
+pub(crate) fn expiry(token: &str) -> Option<i64> {
+    #[derive(serde::Deserialize)]
+    struct ExpiryClaims {
+        exp: i64,
+    }
+
+    insecure_decode::<ExpiryClaims>(token)
+        .ok()
+        .map(|claims| claims.exp)
+}
+
+/// Authentication context of an ID token: its `acr` and `amr` claims, read without verifying it
+#[derive(Default, serde::Deserialize)]
+pub(crate) struct AuthenticationContext {
+    #[serde(default)]
+    pub(crate) acr: Option<String>,
+    #[serde(default)]
+    pub(crate) amr: Vec<String>,
+}
+
+pub(crate) fn authentication_context(id_token: &str) -> AuthenticationContext {
+    insecure_decode::<AuthenticationContext>(id_token).unwrap_or_default()
+}
+
+/// All the claims of an ID token, read without verifying it
+#[cfg(feature = "raw-claims")]
+pub(crate) fn claims(id_token: &str) -> serde_json::Map<String, serde_json::Value> {
+    insecure_decode(id_token).unwrap_or_default()
+}
+
+/// JSON Web Key Set, as served by the JWKS endpoint
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Jwk {
+    #[serde(default)]
+    kid: Option<String>,
+    kty: String,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    alg: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+impl Jwk {
+    /// Whether a token signed with `alg` may be verified with this key, according to its type,
+    /// curve and `alg` if set
+    fn accepts(&self, alg: Algorithm) -> bool {
+        let kty_matches = match (self.kty.as_str(), self.crv.as_deref()) {
+            ("RSA", _) => matches!(
+                alg,
+                Algorithm::RS256
+                    | Algorithm::RS384
+                    | Algorithm::RS512
+                    | Algorithm::PS256
+                    | Algorithm::PS384
+                    | Algorithm::PS512
+            ),
+            ("EC", Some("P-256")) => alg == Algorithm::ES256,
+            ("EC", Some("P-384")) => alg == Algorithm::ES384,
+            ("OKP", Some("Ed25519")) => alg == Algorithm::EdDSA,
+            _ => false,
+        };
+
+        kty_matches
+            && match &self.alg {
+                Some(jwk_alg) => jwk_alg.parse::<Algorithm>().ok() == Some(alg),
+                None => true,
+            }
+    }
+
+    fn decoding_key(&self) -> Option<DecodingKey> {
+        match self.kty.as_str() {
+            "RSA" => DecodingKey::from_rsa_components(self.n.as_ref()?, self.e.as_ref()?).ok(),
+            "EC" => DecodingKey::from_ec_components(self.x.as_ref()?, self.y.as_ref()?).ok(),
+            "OKP" => DecodingKey::from_ed_components(self.x.as_ref()?).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl Jwks {
+    pub(crate) fn contains(&self, kid: &str) -> bool {
+        self.keys.iter().any(|jwk| jwk.kid.as_deref() == Some(kid))
+    }
+
+    /// Key matching the `kid` of a token header, or the only key of the set if the token has no
+    /// `kid`, provided it can verify the header's algorithm
+    fn decoding_key(&self, kid: Option<&str>, alg: Algorithm) -> Option<DecodingKey> {
+        let jwk = match kid {
+            Some(kid) => self
+                .keys
+                .iter()
+                .find(|jwk| jwk.kid.as_deref() == Some(kid))?,
+            None if self.keys.len() == 1 => &self.keys[0],
+            None => return None,
+        };
+
+        if !jwk.accepts(alg) {
+            return None;
+        }
+
+        jwk.decoding_key()
+    }
+}
+
+/// Key of WartID's JWKS verifying a token with the given header
+async fn verifying_key(client: &Client, header: &Header) -> Result<DecodingKey, WartIDError> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        log::error!("[verifying_key] {}", err);
+        WartIDError::InvalidToken
+    };
+
+    // The keys are public: accepting HMAC would let anyone sign tokens with them
+    match header.alg {
+        Algorithm::RS256
+        | Algorithm::RS384
+        | Algorithm::RS512
+        | Algorithm::PS256
+        | Algorithm::PS384
+        | Algorithm::PS512
+        | Algorithm::ES256
+        | Algorithm::ES384
+        | Algorithm::EdDSA => {}
+        alg => return Err(invalid(&format!("unsupported algorithm {:?}", alg))),
+    }
+
+    let jwks = client.request_jwks(header.kid.as_deref()).await?;
+    jwks.decoding_key(header.kid.as_deref(), header.alg)
+        .ok_or_else(|| invalid(&"no matching key in the JWKS"))
+}
+
+/// Audience of a token, one or several clients
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Claims of an access token telling who it was issued to and what it grants, read from the
+/// token itself or answered by WartID's introspection endpoint
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct AccessTokenClaims {
+    #[serde(default)]
+    aud: Option<Audience>,
+    #[serde(default)]
+    azp: Option<String>,
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    pub(crate) scope: Option<String>,
+    #[serde(default)]
+    pub(crate) exp: Option<i64>,
+}
+
+impl AccessTokenClaims {
+    /// Whether the token was issued for the given client, be it its audience (`aud`) or the party
+    /// it was issued to (`azp`, or `client_id` for introspection)
+    pub(crate) fn issued_to(&self, client_id: &str) -> bool {
+        let audience = match &self.aud {
+            Some(Audience::One(aud)) => aud == client_id,
+            Some(Audience::Many(auds)) => auds.iter().any(|aud| aud == client_id),
+            None => false,
+        };
+
+        audience
+            || self.azp.as_deref() == Some(client_id)
+            || self.client_id.as_deref() == Some(client_id)
+    }
+}
+
+/// Verifies the signature and validity period of a JWT access token against WartID's JWKS, and
+/// returns its claims
+///
+/// Its audience is left to [AccessTokenClaims::issued_to], as providers name the client in
+/// different claims.
+pub(crate) async fn verify_access_token(
+    client: &Client,
+    token: &str,
+) -> Result<AccessTokenClaims, WartIDError> {
+    let header = jsonwebtoken::decode_header(token)?;
+    let key = verifying_key(client, &header).await?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.validate_nbf = true;
+    validation.leeway = client.leeway().as_secs();
+
+    Ok(jsonwebtoken::decode::<AccessTokenClaims>(token, &key, &validation)?.claims)
+}
+
+/// Registered claims, not copied to the session's custom claims
+const REGISTERED_CLAIMS: &[&str] = &[
+    "iss",
+    "aud",
+    "exp",
+    "iat",
+    "nbf",
+    "jti",
+    "azp",
+    "nonce",
+    "auth_time",
+    "at_hash",
+    "acr",
+    "amr",
+];
+
+/// Verifies the signature, issuer, audience and validity period of an ID token against WartID's
+/// JWKS, and returns the identity it holds
+///
+/// The issuer is only checked if the client [knows it][Client::issuer]. The client's leeway is
+/// tolerated on `exp`, `nbf` and `iat`, to account for clock skew.
+pub(crate) async fn verify_id_token(
+    client: &Client,
+    id_token: &str,
+    client_id: &str,
+) -> Result<UserInfoResponse, WartIDError> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        log::error!("[verify_id_token] {}", err);
+        WartIDError::InvalidToken
+    };
+
+    let header = jsonwebtoken::decode_header(id_token)?;
+    let key = verifying_key(client, &header).await?;
+
+    let leeway = client.leeway().as_secs();
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[client_id]);
+    if let Some(issuer) = client.issuer() {
+        validation.set_issuer(&[issuer]);
+    }
+    validation.validate_nbf = true;
+    validation.leeway = leeway;
+
+    let mut claims = jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(
+        id_token,
+        &key,
+        &validation,
+    )?
+    .claims;
+
+    // A token issued in the future is either forged or signed by a server with a broken clock
+    let now = chrono::Utc::now().timestamp();
+    if let Some(issued_at) = claims.get("iat").and_then(serde_json::Value::as_i64) {
+        if issued_at > now + leeway as i64 {
+            return Err(invalid(&format!("issued in the future ({})", issued_at)));
+        }
+    }
+
+    for claim in REGISTERED_CLAIMS {
+        claims.remove(*claim);
+    }
+
+    Ok(serde_json::from_value(claims.into())?)
+}
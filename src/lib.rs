@@ -1,30 +1,69 @@
-#[cfg(not(any(feature = "rocket")))]
+#[cfg(not(any(feature = "rocket", feature = "tower")))]
 compile_error!("No feature selected, wartid-client is useless");
 
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("No TLS backend selected, enable either the `rustls` or `native-tls` feature");
+
 #[cfg(feature = "rocket")]
 #[macro_use]
 extern crate rocket as rocket_crate;
 
 mod api;
+mod bearer;
+mod error;
 pub mod handlers;
+pub mod hooks;
+mod jwt;
+pub mod metrics;
+mod redirect;
 #[cfg(feature = "rocket")]
 pub mod rocket;
+mod scope;
+pub mod seal;
+pub mod store;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "tower")]
+pub mod tower;
+mod versioning;
+
+pub use api::{
+    Authorization, Client, ClientBuildError, ClientBuilder, TokenRefresher, TokenResponse,
+    UserInfoResponse,
+};
+pub use error::{ConfigError, EmailError, WartIDError};
+use hooks::{LoginHook, UserAuthorizer};
+use metrics::{Metrics, MetricsSnapshot};
+pub use redirect::sanitize_redirect;
+pub use scope::Scope;
+use seal::SessionSealer;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use store::SessionStore;
+#[cfg(feature = "macros")]
+pub use wartid_client_macros::wartid_protected;
 
 trait HasReferer<'a> {
     fn referer(&'a self) -> &'a str;
 }
 
+#[derive(Clone, Debug)]
 pub struct WIDContextUrls {
     /// Login URL (local)
     pub login: String,
 
     /// Callback URL (local)
     pub callback: String,
+
+    /// Logout URL (local), to be POSTed to, see [handlers::Logout]
+    pub logout: String,
 }
 
 impl WIDContextUrls {
-    /// Assumes the login and callback routes are respectively `/oauth2/wartid/login` and
-    /// `/oauth2/wartid/callback`.
+    /// Assumes the login, callback and logout routes are respectively `/oauth2/wartid/login`,
+    /// `/oauth2/wartid/callback` and `/oauth2/wartid/logout`, as mounted by
+    /// [rocket::routes][crate::rocket::routes].
     ///
     /// The base URL is given without a trailing slash
     pub fn from_base_url(base: &str) -> Self {
@@ -36,50 +75,902 @@ impl WIDContextUrls {
         Self {
             login: format!("{}/oauth2/wartid/login", base),
             callback: format!("{}/oauth2/wartid/callback", base),
+            logout: format!("{}/oauth2/wartid/logout", base),
         }
     }
+
+    /// Path to mount [rocket::routes][crate::rocket::routes] at for the routes to be reachable at
+    /// these URLs, e.g. `/oauth2/wartid` for [from_base_url][WIDContextUrls::from_base_url]
+    ///
+    /// Fails if the URLs don't end with `/login`, `/callback` and `/logout` under the same path.
+    pub fn mount_path(&self) -> Result<String, ConfigError> {
+        let path = |url: &str, route: &str| {
+            let path = match reqwest::Url::parse(url) {
+                Ok(url) => url.path().to_string(),
+                Err(_) if url.starts_with('/') => url.to_string(),
+                Err(_) => return None,
+            };
+
+            path.strip_suffix(route).map(String::from)
+        };
+
+        let base = path(&self.callback, "/callback");
+        if base.is_none()
+            || path(&self.login, "/login") != base
+            || path(&self.logout, "/logout") != base
+        {
+            return Err(ConfigError::UnmountableUrls);
+        }
+
+        Ok(base
+            .filter(|base| !base.is_empty())
+            .unwrap_or_else(|| "/".into()))
+    }
+}
+
+/// WartID's OAuth2 endpoints
+#[derive(Clone, Debug)]
+pub struct WIDContextEndpoints {
+    pub authorize: String,
+    pub token: String,
+    pub userinfo: String,
+}
+
+impl WIDContextEndpoints {
+    /// Endpoints of the WartID instance at the given base URL, without a trailing slash
+    pub fn from_base_url(base: &str) -> Self {
+        debug_assert!(
+            base.chars().rev().next() != Some('/'),
+            "the base url shouldn't end with a slash",
+        );
+
+        Self {
+            authorize: format!("{}/oauth2/authorize", base),
+            token: format!("{}/oauth2/token", base),
+            userinfo: format!("{}/oauth2/userinfo", base),
+        }
+    }
+}
+
+/// Endpoints of the main WartID instance
+impl Default for WIDContextEndpoints {
+    fn default() -> Self {
+        Self::from_base_url("https://id.wp-corp.eu.org")
+    }
 }
 
 /// User app / client credentials
 ///
 /// The [Default][Default] implementation loads them from the `WARTID_CLIENT_ID` and
 /// `WARTID_CLIENT_SECRET` environment variables, panics if they're not set.
+#[derive(Clone, Debug)]
 pub struct WIDContextCredentials {
     pub client_id: String,
-    pub(crate) client_secret: String,
+    pub(crate) client_secret: ClientSecret,
 }
 
 impl WIDContextCredentials {
     pub const fn new(client_id: String, client_secret: String) -> Self {
         Self {
             client_id,
-            client_secret,
+            client_secret: ClientSecret(client_secret),
         }
     }
 }
 
+/// Client secret, which can't end up in logs or error messages by mistake: it's formatted as
+/// `***`, and only [exposed][ClientSecret::expose] to be sent to WartID
+#[derive(Clone)]
+pub(crate) struct ClientSecret(String);
+
+impl ClientSecret {
+    pub(crate) fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for ClientSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+impl fmt::Display for ClientSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
 impl Default for WIDContextCredentials {
     fn default() -> Self {
         use std::env::var;
 
-        Self {
-            client_id: var("WARTID_CLIENT_ID").expect("no WARTID_CLIENT_ID set"),
-            client_secret: var("WARTID_CLIENT_SECRET").expect("no WARTID_CLIENT_SECRET set"),
-        }
+        Self::new(
+            var("WARTID_CLIENT_ID").expect("no WARTID_CLIENT_ID set"),
+            var("WARTID_CLIENT_SECRET").expect("no WARTID_CLIENT_SECRET set"),
+        )
+    }
+}
+
+/// What [WIDContext::validate] does when the callback URL isn't HTTPS
+///
+/// Without HTTPS, tokens transit in the clear and can't be kept in `Secure` cookies. Loopback
+/// hosts (`localhost`, `127.0.0.1`, `[::1]`) are always allowed, for local development.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HttpsPolicy {
+    /// Logs a warning
+    Warn,
+    /// Fails the validation
+    Require,
+    /// Allows plain HTTP, e.g. behind a TLS-terminating proxy that can't be seen from the app
+    AllowInsecure,
+}
+
+impl Default for HttpsPolicy {
+    fn default() -> Self {
+        Self::Warn
+    }
+}
+
+/// Where the identity of users is read from at login
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdentitySource {
+    /// The userinfo endpoint, at the cost of a request on each login
+    Userinfo,
+    /// The claims of the ID token, once its signature is verified against WartID's keys. The
+    /// [Client] needs a [JWKS endpoint][ClientBuilder::jwks], and the login the `openid` scope.
+    /// Its `at_hash` claim, if any, must then match the access token.
+    IdToken,
+}
+
+impl Default for IdentitySource {
+    fn default() -> Self {
+        Self::Userinfo
+    }
+}
+
+/// Whether session guards accept `Authorization: Bearer` access tokens, e.g. from API clients
+/// and mobile apps, in addition to session cookies
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BearerTokens {
+    /// Only session cookies are accepted
+    Ignored,
+    /// The bearer token is used if there is one, or else the session cookies
+    Preferred,
+    /// The bearer token is only used if there are no session cookies
+    Fallback,
+}
+
+impl Default for BearerTokens {
+    fn default() -> Self {
+        Self::Ignored
     }
 }
 
+/// Clones share the same session store and metrics
+#[derive(Clone)]
 pub struct WIDContext {
     pub urls: WIDContextUrls,
     pub credentials: WIDContextCredentials,
+
+    /// Maximum time between two requests of the same session, disabled if `None`
+    pub(crate) idle_timeout: Option<Duration>,
+
+    /// Maximum lifetime of a session since login, disabled if `None`
+    pub(crate) absolute_lifetime: Option<Duration>,
+
+    /// Server-side storage for sessions, cookies are used if `None`
+    pub(crate) session_store: Option<Arc<dyn SessionStore>>,
+
+    /// Seals sessions kept in cookies, instead of relying on the framework's cookie encryption
+    pub(crate) sealer: Option<SessionSealer>,
+
+    /// Key of the MAC signing the state of login flows, random unless set
+    pub(crate) state_key: [u8; 32],
+
+    pub(crate) metrics: Arc<Metrics>,
+
+    /// Page the user is sent to when the callback fails, a bare error status is returned if `None`
+    pub(crate) callback_error_redirect: Option<String>,
+
+    /// Page the user is sent to once logged in, when the login didn't say where to
+    pub(crate) post_login_redirect: String,
+
+    pub(crate) client: Client,
+
+    /// Prefix of the cookie names
+    pub(crate) cookie_prefix: String,
+
+    /// Max-age of the session cookies, which are session cookies if `None`
+    pub(crate) cookie_max_age: Option<Duration>,
+
+    /// Whether the cookies are `SameSite=None; Secure; Partitioned`, for apps embedded in iframes
+    pub(crate) partitioned_cookies: bool,
+
+    /// Whether the session cookies are `SameSite=Strict`
+    pub(crate) strict_cookies: bool,
+
+    /// Whether the logout route also answers `GET` requests
+    pub(crate) get_logout: bool,
+
+    /// Path of the session cookies
+    pub(crate) session_cookie_path: String,
+
+    /// Tolerance on the 10 minutes users have to log in, for slow users and clock drift
+    pub(crate) login_flow_leeway: Duration,
+
+    /// Path of the cookies of the login flow, the callback's path if `None`
+    pub(crate) flow_cookie_path: Option<String>,
+
+    /// Whether to reject logins with an unverified email
+    pub(crate) require_verified_email: bool,
+
+    /// How long userinfo is cached in the session before being requested again, forever if `None`
+    pub(crate) userinfo_ttl: Option<Duration>,
+
+    pub(crate) https_policy: HttpsPolicy,
+
+    pub(crate) login_hook: Option<Arc<dyn LoginHook>>,
+
+    pub(crate) user_authorizer: Option<Arc<dyn UserAuthorizer>>,
+
+    /// Lowercase domains the verified email of users must belong to, any if empty
+    pub(crate) allowed_email_domains: Vec<String>,
+
+    pub(crate) identity_source: IdentitySource,
+
+    pub(crate) bearer_tokens: BearerTokens,
+
+    /// Whether session guards forward rather than fail when no one is logged in
+    pub(crate) forward_logged_out: bool,
+
+    /// Whether the `X-Forwarded-Proto` and `X-Forwarded-Host` headers are trusted
+    pub(crate) trust_forwarded_headers: bool,
+}
+
+impl fmt::Debug for WIDContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WIDContext")
+            .field("urls", &self.urls)
+            .field("credentials", &self.credentials)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("absolute_lifetime", &self.absolute_lifetime)
+            .field("session_store", &self.session_store.is_some())
+            .field("sealer", &self.sealer.is_some())
+            .field("callback_error_redirect", &self.callback_error_redirect)
+            .field("post_login_redirect", &self.post_login_redirect)
+            .field("client", &self.client)
+            .field("cookie_prefix", &self.cookie_prefix)
+            .field("cookie_max_age", &self.cookie_max_age)
+            .field("partitioned_cookies", &self.partitioned_cookies)
+            .field("strict_cookies", &self.strict_cookies)
+            .field("get_logout", &self.get_logout)
+            .field("session_cookie_path", &self.session_cookie_path)
+            .field("login_flow_leeway", &self.login_flow_leeway)
+            .field("flow_cookie_path", &self.flow_cookie_path)
+            .field("require_verified_email", &self.require_verified_email)
+            .field("userinfo_ttl", &self.userinfo_ttl)
+            .field("https_policy", &self.https_policy)
+            .field("login_hook", &self.login_hook.is_some())
+            .field("user_authorizer", &self.user_authorizer.is_some())
+            .field("allowed_email_domains", &self.allowed_email_domains)
+            .field("identity_source", &self.identity_source)
+            .field("bearer_tokens", &self.bearer_tokens)
+            .field("forward_logged_out", &self.forward_logged_out)
+            .field("trust_forwarded_headers", &self.trust_forwarded_headers)
+            .finish()
+    }
+}
+
+impl WIDContext {
+    pub fn new(urls: WIDContextUrls, credentials: WIDContextCredentials) -> Self {
+        Self {
+            urls,
+            credentials,
+            idle_timeout: None,
+            absolute_lifetime: None,
+            session_store: None,
+            sealer: None,
+            state_key: rand::random(),
+            metrics: Arc::default(),
+            callback_error_redirect: None,
+            post_login_redirect: "/".into(),
+            client: Client::default(),
+            cookie_prefix: "wartid".into(),
+            cookie_max_age: None,
+            partitioned_cookies: false,
+            strict_cookies: false,
+            get_logout: false,
+            session_cookie_path: "/".into(),
+            login_flow_leeway: Duration::from_secs(30),
+            flow_cookie_path: None,
+            require_verified_email: false,
+            userinfo_ttl: None,
+            https_policy: HttpsPolicy::default(),
+            login_hook: None,
+            user_authorizer: None,
+            allowed_email_domains: Vec::new(),
+            identity_source: IdentitySource::default(),
+            bearer_tokens: BearerTokens::default(),
+            forward_logged_out: false,
+            trust_forwarded_headers: false,
+        }
+    }
+
+    /// Builds a context from the environment variables:
+    ///   * `WARTID_CLIENT_ID` and `WARTID_CLIENT_SECRET`, the [credentials][WIDContextCredentials]
+    ///   * `WARTID_BASE_URL`, the base URL of the app, see [WIDContextUrls::from_base_url]
+    ///   * `WARTID_ISSUER`, optionally, the URL of the WartID instance to use instead of the main
+    ///     one, whose endpoints are [discovered][ClientBuilder::discover]
+    ///
+    /// The context can still be customized with the other `with_*` methods.
+    pub async fn from_env() -> Result<Self, ConfigError> {
+        fn var(name: &'static str) -> Result<String, ConfigError> {
+            std::env::var(name).map_err(|_| ConfigError::MissingEnv(name))
+        }
+
+        let credentials =
+            WIDContextCredentials::new(var("WARTID_CLIENT_ID")?, var("WARTID_CLIENT_SECRET")?);
+        let urls = WIDContextUrls::from_base_url(var("WARTID_BASE_URL")?.trim_end_matches('/'));
+
+        let context = Self::new(urls, credentials);
+
+        match var("WARTID_ISSUER") {
+            Ok(issuer) => {
+                let client = Client::builder()
+                    .discover(&issuer)
+                    .await?
+                    .build()
+                    .map_err(ConfigError::InvalidClient)?;
+                Ok(context.with_client(client))
+            }
+            Err(_) => Ok(context),
+        }
+    }
+
+    /// Makes session guards forward to the next matching route when no one is logged in, instead
+    /// of failing with `401 Unauthorized`, so anonymous and authenticated routes can share a path
+    pub fn with_forward_when_logged_out(mut self) -> Self {
+        self.forward_logged_out = true;
+        self
+    }
+
+    /// Sets where the identity of users is read from at login, the userinfo endpoint by default
+    pub fn with_identity_source(mut self, source: IdentitySource) -> Self {
+        self.identity_source = source;
+        self
+    }
+
+    /// Lets session guards authenticate requests with an `Authorization: Bearer` access token
+    /// issued for this app, checked against WartID on each request. They're ignored by default.
+    ///
+    /// Opaque access tokens need the client's
+    /// [introspection endpoint][ClientBuilder::introspection] to be set.
+    pub fn with_bearer_tokens(mut self, bearer_tokens: BearerTokens) -> Self {
+        self.bearer_tokens = bearer_tokens;
+        self
+    }
+
+    /// Calls the given hook on each successful login
+    pub fn with_login_hook(mut self, hook: impl LoginHook + 'static) -> Self {
+        self.login_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Lets the given authorizer refuse logins: refused users get no session, and the callback
+    /// fails with [AccessDenied][WartIDError::AccessDenied], sending them to the
+    /// [callback error page][WIDContext::with_callback_error_redirect] if there's one
+    pub fn with_user_authorizer(mut self, authorizer: impl UserAuthorizer + 'static) -> Self {
+        self.user_authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
+    /// Only lets users with a verified email in one of the given domains (e.g. `wp-corp.eu.org`)
+    /// log in, refusing the others like a [user authorizer][WIDContext::with_user_authorizer]
+    ///
+    /// The email must be requested with [Login::with_email][handlers::Login::with_email]: users
+    /// without a verified email are refused, as there's no telling where they belong.
+    pub fn with_allowed_email_domains<I, D>(mut self, domains: I) -> Self
+    where
+        I: IntoIterator<Item = D>,
+        D: Into<String>,
+    {
+        self.allowed_email_domains = domains
+            .into_iter()
+            .map(|domain| domain.into().to_lowercase())
+            .collect();
+        self
+    }
+
+    /// Whether the email is verified and in one of the
+    /// [allowed domains][WIDContext::with_allowed_email_domains], if any are set
+    pub(crate) fn email_domain_allowed(&self, session: &WartIDSession) -> bool {
+        if self.allowed_email_domains.is_empty() {
+            return true;
+        }
+
+        let domain = match (&session.email, session.email_verified) {
+            (Some(email), true) => email.rsplit_once('@').map(|(_, domain)| domain),
+            _ => None,
+        };
+
+        domain.map_or(false, |domain| {
+            self.allowed_email_domains
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+        })
+    }
+
+    /// Sets how strictly [validate][WIDContext::validate] enforces an HTTPS callback URL
+    pub fn with_https_policy(mut self, policy: HttpsPolicy) -> Self {
+        self.https_policy = policy;
+        self
+    }
+
+    /// Checks the configuration for dangerous mistakes, to be called once at startup
+    ///
+    /// A callback URL over plain HTTP is handled according to the [HttpsPolicy].
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.identity_source == IdentitySource::IdToken && self.client.jwks_url().is_none() {
+            return Err(ConfigError::MissingJwks);
+        }
+
+        let callback = reqwest::Url::parse(&self.urls.callback)
+            .map_err(|_| ConfigError::InvalidCallback(self.urls.callback.clone()))?;
+
+        let loopback = match callback.host() {
+            Some(url::Host::Domain(domain)) => domain == "localhost",
+            Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+            None => false,
+        };
+
+        if callback.scheme() == "https" || loopback {
+            return Ok(());
+        }
+
+        match self.https_policy {
+            HttpsPolicy::Warn => {
+                log::warn!(
+                    "[WIDContext::validate] callback URL {} isn't HTTPS, tokens may leak",
+                    self.urls.callback,
+                );
+                Ok(())
+            }
+            HttpsPolicy::Require => Err(ConfigError::InsecureCallback(self.urls.callback.clone())),
+            HttpsPolicy::AllowInsecure => Ok(()),
+        }
+    }
+
+    /// Requests userinfo again when the session's copy is older than `ttl`, so changes made on
+    /// WartID (name, email...) show up without logging out
+    pub fn with_userinfo_refresh(mut self, ttl: Duration) -> Self {
+        self.userinfo_ttl = Some(ttl);
+        self
+    }
+
+    /// Rejects logins of users whose email isn't verified
+    pub fn with_verified_email_required(mut self) -> Self {
+        self.require_verified_email = true;
+        self
+    }
+
+    /// Scopes the cookies of this context to the given tenant, so users of several contexts in
+    /// the same app don't get their sessions mixed up
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.cookie_prefix = format!("wartid_{}", tenant);
+        self
+    }
+
+    /// Keeps the session cookies for the given duration, so sessions survive browser restarts
+    /// while the refresh token is valid. They're session cookies by default.
+    pub fn with_cookie_max_age(mut self, max_age: Duration) -> Self {
+        self.cookie_max_age = Some(max_age);
+        self
+    }
+
+    /// Makes the cookies `SameSite=None; Secure; Partitioned` (CHIPS), so sessions work in apps
+    /// embedded in an iframe on another site, where browsers block third-party cookies
+    ///
+    /// Browsers drop `SameSite=None` cookies that aren't `Secure`, so the app must be served over
+    /// HTTPS. The `Partitioned` attribute is added by the
+    /// [PartitionedCookies][crate::rocket::PartitionedCookies] fairing, which must be attached.
+    /// Browsers that don't support CHIPS ignore it, and then treat the cookies as plain
+    /// third-party cookies, which they may block.
+    pub fn with_partitioned_cookies(mut self) -> Self {
+        self.partitioned_cookies = true;
+        self
+    }
+
+    /// Reads the public scheme and host of requests from their `X-Forwarded-Proto` and
+    /// `X-Forwarded-Host` headers, for apps behind a reverse proxy. They're used to accept
+    /// redirection targets given as absolute URLs on the app's own origin.
+    ///
+    /// Only enable this if the proxy overwrites these headers, as clients could otherwise spoof
+    /// them. The scheme of the [callback url][WIDContextUrls::callback] and the `Host` header are
+    /// used otherwise.
+    pub fn with_trusted_forwarded_headers(mut self) -> Self {
+        self.trust_forwarded_headers = true;
+        self
+    }
+
+    /// Makes the session cookies `SameSite=Strict` rather than `Lax`, so they aren't sent along
+    /// any request coming from another site, even top-level navigations
+    ///
+    /// Only the session cookies are: the ones of the login flow stay `Lax`, as WartID redirecting
+    /// to the callback is a cross-site navigation which `Strict` cookies wouldn't reach, breaking
+    /// logins. For the same reason, the callback then sends users to their page with a same-site
+    /// navigation (an HTML page refreshing to it) instead of a redirection, or they'd look logged
+    /// out on that first page.
+    ///
+    /// Links to the app from other sites land on logged out pages, until the user navigates on it.
+    /// Ignored with [partitioned cookies][WIDContext::with_partitioned_cookies], which must be
+    /// `SameSite=None`.
+    pub fn with_strict_cookies(mut self) -> Self {
+        self.strict_cookies = true;
+        self
+    }
+
+    /// Keeps the logout route answering `GET` requests, as it did before only accepting `POST`
+    /// ones, for apps still linking to it
+    ///
+    /// Any site can then log users out, e.g. with a mere `<img src="/logout">`.
+    pub fn with_get_logout(mut self) -> Self {
+        self.get_logout = true;
+        self
+    }
+
+    /// Sets the tolerance on the 10 minutes users have to log in on WartID and come back to the
+    /// callback, 30 seconds by default. Past it, the callback restarts the login flow.
+    ///
+    /// The callback checks it against the time the flow started, rather than relying on browsers
+    /// expiring the cookies of the flow.
+    pub fn with_login_flow_leeway(mut self, leeway: Duration) -> Self {
+        self.login_flow_leeway = leeway;
+        self
+    }
+
+    /// Sets the `Path` of the session cookies, `/` by default, for apps only needing sessions
+    /// under a part of the site
+    pub fn with_session_cookie_path(mut self, path: &str) -> Self {
+        self.session_cookie_path = path.to_string();
+        self
+    }
+
+    /// Sets the `Path` of the short-lived cookies of the login flow (state, requested scopes...),
+    /// which default to the path of the [callback][WIDContextUrls::callback], the only route
+    /// reading them
+    pub fn with_flow_cookie_path(mut self, path: &str) -> Self {
+        self.flow_cookie_path = Some(path.to_string());
+        self
+    }
+
+    pub(crate) fn flow_cookie_path(&self) -> String {
+        if let Some(path) = &self.flow_cookie_path {
+            return path.clone();
+        }
+
+        match reqwest::Url::parse(&self.urls.callback) {
+            Ok(url) => url.path().to_string(),
+            Err(_) if self.urls.callback.starts_with('/') => self.urls.callback.clone(),
+            Err(_) => "/".into(),
+        }
+    }
+
+    /// Replaces the default [Client], which targets the main WartID instance
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Redirects users to the given page instead of returning a bare error status when logging
+    /// in fails. The [error code][WartIDError::code] is passed in the `error` query parameter, so
+    /// the page can be tailored, and the ID of the login flow in `request_id`, so users can quote
+    /// it to support and have it matched with the logs. That ID comes from the `X-Request-Id`
+    /// header of the login request when set, e.g. by a reverse proxy.
+    pub fn with_callback_error_redirect(mut self, url: impl Into<String>) -> Self {
+        self.callback_error_redirect = Some(url.into());
+        self
+    }
+
+    /// Sets where users are sent once logged in, when neither the [Login][handlers::Login] handler
+    /// nor the `next` parameter of the login request say where to, `/` by default
+    pub fn with_post_login_redirect(mut self, path: impl Into<String>) -> Self {
+        self.post_login_redirect = path.into();
+        self
+    }
+
+    /// Keeps sessions and tokens in the given store, only putting a session id in the cookies
+    pub fn with_session_store(mut self, store: impl SessionStore + 'static) -> Self {
+        self.session_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Seals the session and tokens kept in cookies with the given key, see [SessionSealer]
+    ///
+    /// Ignored when a [session store][WIDContext::with_session_store] is set.
+    pub fn with_session_sealer(mut self, key: &[u8; 32]) -> Self {
+        self.sealer = Some(SessionSealer::new(key));
+        self
+    }
+
+    /// Signs the state of login flows with the given key, instead of one picked at random when the
+    /// context is built
+    ///
+    /// The state can only be checked by a context with the same key: apps running several
+    /// instances must give them all the same one, or users finishing their login on another
+    /// instance than the one they started it on are refused. Flows started before a restart are
+    /// refused too unless the key is kept. It must be kept secret, and differ from the client
+    /// secret, which WartID also knows.
+    pub fn with_state_key(mut self, key: &[u8; 32]) -> Self {
+        self.state_key = *key;
+        self
+    }
+
+    /// Logs users out after they've been inactive for the given duration
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Logs users out once the given duration has elapsed since they logged in, even if their
+    /// tokens are still being refreshed
+    pub fn with_absolute_lifetime(mut self, lifetime: Duration) -> Self {
+        self.absolute_lifetime = Some(lifetime);
+        self
+    }
+
+    pub(crate) fn cookie_name(&self, suffix: &str) -> String {
+        format!("{}_{}", self.cookie_prefix, suffix)
+    }
+
+    /// Current values of the authentication counters
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Returns `true` if the session exceeded either the idle timeout or the absolute lifetime
+    ///
+    /// Timestamps at 0, of sessions saved before they were tracked, aren't checked.
+    pub(crate) fn session_timed_out(&self, session: &WartIDSession, now: i64) -> bool {
+        let exceeds = |since: i64, limit: Option<Duration>| match limit {
+            Some(limit) if since != 0 => now.saturating_sub(since) > limit.as_secs() as i64,
+            _ => false,
+        };
+
+        exceeds(session.last_seen, self.idle_timeout)
+            || exceeds(session.issued_at, self.absolute_lifetime)
+    }
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub struct WartIDSession {
-    pub id: uuid::Uuid,
+    /// Subject identifier of the user, a UUID for WartID but an opaque string for other providers
+    ///
+    /// This used to be a `Uuid`. Apps should move to [sub][WartIDSession::sub], or
+    /// [sub_uuid][WartIDSession::sub_uuid] until they support other subjects: the field will be
+    /// made private in a later version.
+    pub id: String,
     pub name: String,
     pub email: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) preferred_username: Option<String>,
+
+    /// URL of the user's profile picture
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) picture: Option<String>,
+
+    /// Scopes granted by WartID, space-separated
     pub scopes: String,
+
+    /// Scopes requested at login, space-separated
+    #[serde(default)]
+    pub(crate) requested_scopes: String,
+
+    #[serde(default)]
+    pub(crate) email_verified: bool,
+
+    /// Login timestamp
+    #[serde(default)]
+    pub(crate) issued_at: i64,
+
+    /// Timestamp of the last request made with this session, only kept up to date when an
+    /// [idle timeout][WIDContext::with_idle_timeout] is set
+    #[serde(default)]
+    pub(crate) last_seen: i64,
+
+    /// Timestamp of the last userinfo request
+    #[serde(default)]
+    pub(crate) userinfo_fetched_at: i64,
+
+    /// Whether the user asked to be [remembered][handlers::Login::remember_me]
+    #[serde(default)]
+    pub(crate) remember_me: bool,
+
+    /// Expiry timestamp of the access token, if WartID gave one or it's a JWT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) token_expires_at: Option<i64>,
+
+    /// Authentication context class reference of the login, from the ID token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) acr: Option<String>,
+
+    /// Authentication methods used to log in, from the ID token
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) amr: Vec<String>,
+
+    /// ID token issued at login, if WartID returned one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) id_token: Option<String>,
+
+    /// Userinfo fields not known by this crate, like app-specific claims
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub(crate) extra: serde_json::Map<String, serde_json::Value>,
+
+    /// Every claim of the userinfo and the ID token, the userinfo ones taking precedence
+    #[cfg(feature = "raw-claims")]
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub(crate) raw_claims: serde_json::Map<String, serde_json::Value>,
+}
+
+impl WartIDSession {
+    /// Username the user wants to be referred to as, if WartID returned one
+    pub fn preferred_username(&self) -> Option<&str> {
+        self.preferred_username.as_deref()
+    }
+
+    /// URL of the user's avatar, from the `picture` claim, if WartID returned one
+    pub fn avatar_url(&self) -> Option<&str> {
+        self.picture.as_deref()
+    }
+
+    /// Subject identifier of the user, as given by WartID
+    pub fn sub(&self) -> &str {
+        &self.id
+    }
+
+    /// The [subject][WartIDSession::sub] as a UUID, which it is for users of WartID, or `None` for
+    /// the opaque subjects of other providers
+    pub fn sub_uuid(&self) -> Option<uuid::Uuid> {
+        self.id.parse().ok()
+    }
+
+    #[deprecated(note = "use `sub_uuid`")]
+    pub fn uuid(&self) -> Option<uuid::Uuid> {
+        self.sub_uuid()
+    }
+
+    /// Name to greet the user with: their name, or else their preferred username, their email or
+    /// their id, so UIs always have something to show
+    pub fn display_name(&self) -> std::borrow::Cow<'_, str> {
+        if !self.name.is_empty() {
+            return self.name.as_str().into();
+        }
+
+        match (&self.preferred_username, &self.email) {
+            (Some(username), _) if !username.is_empty() => username.as_str().into(),
+            (_, Some(email)) if !email.is_empty() => email.as_str().into(),
+            _ => self.id.as_str().into(),
+        }
+    }
+
+    /// Whether WartID verified the user owns the [email][WartIDSession::email]
+    ///
+    /// Don't match accounts on an unverified email, anyone could have set it.
+    pub fn email_verified(&self) -> bool {
+        self.email_verified
+    }
+
+    /// The user's email, failing with the reason it's missing: either WartID didn't grant the
+    /// `email` scope, or it did but the user has no email
+    ///
+    /// Unlike [email][WartIDSession::email], this tells a refused scope apart from an absent
+    /// email. It doesn't check the email is [verified][WartIDSession::email_verified].
+    pub fn email_or_err(&self) -> Result<&str, EmailError> {
+        if !self.has_scope(Scope::Email.as_str()) {
+            return Err(EmailError::NotGranted);
+        }
+
+        self.email.as_deref().ok_or(EmailError::NotProvided)
+    }
+
+    /// Raw ID token issued at login, needed for RP-initiated logout (`id_token_hint`) or to pass
+    /// the user's identity to other services
+    pub fn id_token(&self) -> Option<&str> {
+        self.id_token.as_deref()
+    }
+
+    /// Expiry timestamp of the access token, if known
+    ///
+    /// Tokens are refreshed by the session guard, so this is mostly useful to frontends scheduling
+    /// a silent renew before the token expires rather than reacting to a 401.
+    pub fn token_expires_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        use chrono::TimeZone;
+
+        self.token_expires_at
+            .map(|timestamp| chrono::Utc.timestamp(timestamp, 0))
+    }
+
+    /// Whether the access token is expired, `false` if its expiry isn't known
+    pub fn is_token_expired(&self) -> bool {
+        self.token_expires_at.map_or(false, |timestamp| {
+            timestamp <= chrono::Utc::now().timestamp()
+        })
+    }
+
+    /// Whether WartID granted the given scope
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes
+            .split_whitespace()
+            .any(|granted| granted == scope)
+    }
+
+    /// Scopes requested at login that WartID didn't grant, e.g. `email` if the user refused to
+    /// share it
+    pub fn missing_scopes(&self) -> Vec<&str> {
+        self.requested_scopes
+            .split_whitespace()
+            .filter(|scope| !self.has_scope(scope))
+            .collect()
+    }
+
+    /// Authentication context class the user logged in with (`acr` claim of the ID token), if
+    /// WartID returned one
+    pub fn acr(&self) -> Option<&str> {
+        self.acr.as_deref()
+    }
+
+    /// Authentication methods the user logged in with (`amr` claim of the ID token), e.g. `pwd`
+    /// or `otp`
+    pub fn amr(&self) -> &[String] {
+        &self.amr
+    }
+
+    /// Fails with [InsufficientAuthentication][WartIDSessionError::InsufficientAuthentication] if
+    /// the user didn't log in with one of the given authentication context classes, for routes
+    /// requiring e.g. MFA. Users can then be sent to a login
+    /// [requesting it][handlers::Login::with_acr_values].
+    pub fn require_acr(&self, accepted: &[&str]) -> Result<&Self, WartIDSessionError> {
+        match &self.acr {
+            Some(acr) if accepted.contains(&acr.as_str()) => Ok(self),
+            _ => Err(WartIDSessionError::InsufficientAuthentication),
+        }
+    }
+
+    /// Userinfo fields not known by this crate, empty unless WartID returns app-specific claims
+    pub fn extra(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.extra
+    }
+
+    /// Deserializes the custom claim `name`, returns `None` if it's missing or doesn't fit `T`
+    ///
+    /// With the `raw-claims` feature, claims of the ID token are looked up as well.
+    ///
+    /// ```
+    /// # fn example(session: &wartid_client::WartIDSession) {
+    /// let tenant: Option<String> = session.claim("tenant_id");
+    /// # }
+    /// ```
+    pub fn claim<T: serde::de::DeserializeOwned>(&self, name: &str) -> Option<T> {
+        #[cfg(feature = "raw-claims")]
+        let value = self.extra.get(name).or_else(|| self.raw_claims.get(name))?;
+        #[cfg(not(feature = "raw-claims"))]
+        let value = self.extra.get(name)?;
+
+        serde_json::from_value(value.clone())
+            .map_err(|err| log::error!("[WartIDSession::claim] {}: {}", name, err))
+            .ok()
+    }
+
+    /// Every claim of the userinfo and of the ID token, as returned by WartID, for claims the
+    /// typed fields don't model. Userinfo claims take precedence over the ID token ones.
+    ///
+    /// They're kept in the session, making it bigger, hence the `raw-claims` feature.
+    #[cfg(feature = "raw-claims")]
+    pub fn raw_claims(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.raw_claims
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -87,18 +978,59 @@ pub enum WartIDSessionError {
     MissingAuthorization,
     MissingRefresh,
     MissingUserinfo,
+    /// The session cookies can't be opened, e.g. because they were tampered with. They're
+    /// cleared, so the user is considered logged out.
     SessionDecoding,
+    /// Refreshing the tokens failed for another reason than WartID being unreachable or rejecting
+    /// the refresh token
     Refreshing,
+    /// WartID couldn't be reached to refresh the tokens, the session may still be valid
+    Network,
+    /// WartID rejected the refresh token, e.g. because it expired or was revoked
+    RefreshRejected,
+    /// The session exceeded the idle timeout or the absolute lifetime set in the [WIDContext]
+    Expired,
+    /// The [SessionStore] failed to read the session
+    SessionStore,
+    /// Refreshed tokens were issued for another user than the one of the session
+    SubjectMismatch,
+    /// WartID rejected the session's tokens, e.g. because they were revoked
+    Revoked,
+    /// The bearer token couldn't be verified, or was issued for another client
+    InvalidBearer,
+    /// The user didn't log in with a strong enough authentication, see
+    /// [WartIDSession::require_acr]
+    InsufficientAuthentication,
+    /// The session was saved by a version of the app it can't be read by anymore, the user has
+    /// to log in again
+    Outdated,
+    /// No [WIDContext] is managed for the request, see
+    /// [ContextResolver][crate::rocket::ContextResolver]
+    MissingContext,
 }
 
 impl WartIDSessionError {
     /// Returns `true` if this error can be considered as the user being logged out
     ///
-    /// This effectively returns `true` when one of the session-related cookies are missing
+    /// This effectively returns `true` when one of the session-related cookies are missing, or
+    /// can't be read anymore
     pub fn is_logged_out(self) -> bool {
         match self {
-            Self::MissingAuthorization | Self::MissingRefresh | Self::MissingUserinfo => true,
-            Self::SessionDecoding | Self::Refreshing => false,
+            Self::MissingAuthorization
+            | Self::MissingRefresh
+            | Self::MissingUserinfo
+            | Self::SessionDecoding
+            | Self::Expired
+            | Self::SubjectMismatch
+            | Self::Revoked
+            | Self::InvalidBearer
+            | Self::RefreshRejected
+            | Self::Outdated => true,
+            Self::Refreshing
+            | Self::Network
+            | Self::SessionStore
+            | Self::InsufficientAuthentication
+            | Self::MissingContext => false,
         }
     }
 }
@@ -121,4 +1053,4 @@ impl WartIDSessionError {
 ///     Ok(format!("Your name id: {}", &session.name))
 /// }
 /// ```
-pub struct WartIDSessionOrRedirect<'a>(Option<&'a WartIDSession>);
+pub struct WartIDSessionOrRedirect<'a>(Option<&'a WartIDSession>, &'a str);
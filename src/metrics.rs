@@ -0,0 +1,89 @@
+//! # OAuth metrics
+//!
+//! Counters of the authentication events handled by the crate, readable through
+//! [WIDContext::metrics][crate::WIDContext::metrics]. A spike in refresh failures usually means
+//! the IdP is down or the client secret is wrong.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    login_initiations: AtomicU64,
+    successful_callbacks: AtomicU64,
+    failed_callbacks: AtomicU64,
+    refreshes: AtomicU64,
+    refresh_failures: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn login_initiated(&self) {
+        self.login_initiations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn callback_succeeded(&self) {
+        self.successful_callbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn callback_failed(&self) {
+        self.failed_callbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn refreshed(&self) {
+        self.refreshes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn refresh_failed(&self) {
+        self.refresh_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            login_initiations: self.login_initiations.load(Ordering::Relaxed),
+            successful_callbacks: self.successful_callbacks.load(Ordering::Relaxed),
+            failed_callbacks: self.failed_callbacks.load(Ordering::Relaxed),
+            refreshes: self.refreshes.load(Ordering::Relaxed),
+            refresh_failures: self.refresh_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Values of the counters at a given time
+///
+/// The [Display][fmt::Display] implementation renders them in the Prometheus text format, so it
+/// can be returned as is from a `/metrics` route.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Number of redirections to the IdP's authorization page
+    pub login_initiations: u64,
+    /// Number of users that came back from the IdP and got a session
+    pub successful_callbacks: u64,
+    /// Number of users that came back from the IdP but couldn't get a session
+    pub failed_callbacks: u64,
+    /// Number of successful token refreshes
+    pub refreshes: u64,
+    /// Number of failed token refreshes
+    pub refresh_failures: u64,
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counters = [
+            ("wartid_login_initiations_total", self.login_initiations),
+            (
+                "wartid_successful_callbacks_total",
+                self.successful_callbacks,
+            ),
+            ("wartid_failed_callbacks_total", self.failed_callbacks),
+            ("wartid_refreshes_total", self.refreshes),
+            ("wartid_refresh_failures_total", self.refresh_failures),
+        ];
+
+        for (name, value) in counters.iter() {
+            writeln!(f, "# TYPE {} counter", name)?;
+            writeln!(f, "{} {}", name, value)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+/// Returns the redirection target if it's a path on the current origin, to avoid open redirects
+///
+/// Only absolute paths are accepted: anything with a scheme (`https:`, `javascript:`...), a
+/// protocol-relative URL (`//evil.com`), or a path browsers would read as one (`/\evil.com`,
+/// control characters which they strip) is rejected.
+pub fn sanitize_redirect(path: &str) -> Option<String> {
+    let same_origin = path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.contains('\\')
+        && !path.chars().any(char::is_control);
+
+    if same_origin {
+        Some(path.to_string())
+    } else {
+        None
+    }
+}
@@ -15,59 +15,480 @@
 //!     [handlers::Login][crate::handlers::Login]
 //!   * Have a [callback url][crate::WIDContextUrls::callback] route that responds with a
 //!     [handlers::Callback][crate::handlers::Callback]
+//!   * Have a [logout url][crate::WIDContextUrls::logout] route that responds with a
+//!     [handlers::Logout][crate::handlers::Logout]
+//!
+//! [routes] mounts all three.
 //!
 //! ## What will you be able to do ?
 //!
 //!   * Use request guards to obtain information about the current session
-//!       * `WartIDSession` gives you a valid session or fails with `401 Unauthorized`
+//!       * `&WartIDSession` gives you a valid session or fails with `401 Unauthorized`, and
+//!         `WartIDSession` an owned copy of it
 //!       * `Option<WartIDSession>` or `Result<WartIDSession, WartIDError>` doesn't fail if no one
 //!         is authenticated
+//!       * With [forwarding][crate::WIDContext::with_forward_when_logged_out] enabled, the session
+//!         guards forward instead of failing when no one is logged in, so an authenticated route
+//!         and an anonymous one with a higher rank can share a path. `Result` guards then forward
+//!         as well, while `Option` guards still yield `None`. Other errors (e.g. WartID being
+//!         unreachable) still fail.
+//!       * With [bearer tokens][crate::WIDContext::with_bearer_tokens] enabled, the session guards
+//!         also accept requests carrying an `Authorization: Bearer` access token, e.g. from API
+//!         clients, whose session isn't stored
+//!       * [session_from_cookies] reads the session where guards can't be used, e.g. in GraphQL
+//!         resolvers
 //!       * `WartIDSessionOrLogin` redirects the user to the login page if no WartIDSession is
 //!         active, or contains a `WartIDSession`
 
-use crate::api::{Authorization, Client};
+use crate::api::Authorization;
+use crate::bearer::{authenticate_bearer, bearer_token};
 use crate::handlers::*;
-use crate::{WIDContext, WartIDSession, WartIDSessionError, WartIDSessionOrRedirect};
+use crate::jwt;
+use crate::scope;
+use crate::seal::SealError;
+use crate::store::{new_session_id, StoredSession};
+use crate::versioning;
+use crate::{
+    sanitize_redirect, BearerTokens, ConfigError, IdentitySource, Scope, WIDContext, WartIDError,
+    WartIDSession, WartIDSessionError, WartIDSessionOrRedirect,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use rocket::fairing::{Fairing, Info, Kind};
 use rocket::handler::Handler;
-use rocket::http::{Cookie, Method, SameSite, Status};
+use rocket::http::{Cookie, CookieJar, Method, SameSite, Status};
 use rocket::request::{FromRequest, Outcome};
+use rocket::response::content::Html;
 use rocket::response::{Redirect, Responder};
-use rocket::{Data, Request, Route};
+use rocket::{Data, Request, Response, Route};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
 
-pub fn routes(with_email: bool) -> Vec<Route> {
-    let login = if with_email {
-        Login::basic().with_email()
-    } else {
-        Login::basic()
-    };
+/// Login, callback and logout routes, logins being configured by the given [Login], e.g.
+/// `routes(Login::basic().with_email())`
+///
+/// The callback also accepts `POST` requests if the login uses
+/// [form_post][Login::with_form_post]. Logout is only reachable through `POST`, see [Logout].
+pub fn routes(login: Login) -> Vec<Route> {
+    routes_with_logout(login, Some(Logout(None)))
+}
+
+#[deprecated(note = "`routes` takes a `Login` now")]
+pub fn routes_with(login: Login) -> Vec<Route> {
+    routes(login)
+}
 
-    vec![
+/// Validates the context and mounts the routes where its URLs point to, so they can't disagree
+///
+/// ```no_run
+/// # let context: wartid_client::WIDContext = unimplemented!();
+/// use wartid_client::handlers::Login;
+///
+/// let rocket = wartid_client::rocket::mount(rocket::ignite(), context, Login::basic())
+///     .expect("invalid WartID configuration");
+/// ```
+pub fn mount(
+    rocket: rocket::Rocket,
+    context: WIDContext,
+    login: Login,
+) -> Result<rocket::Rocket, ConfigError> {
+    context.validate()?;
+    let path = context.urls.mount_path()?;
+
+    Ok(rocket.manage(context).mount(&path, routes(login)))
+}
+
+/// Same as [routes], using the given [Logout] for the logout route, or not mounting one if
+/// `None` for apps handling logout themselves
+pub fn routes_with_logout(login: Login, logout: Option<Logout>) -> Vec<Route> {
+    let form_post = login.form_post;
+
+    let mut routes = vec![
         Route::new(Method::Get, "/login", login),
         Route::new(Method::Get, "/callback", Callback),
-    ]
+    ];
+
+    if let Some(logout) = logout {
+        // Only answered if the context allows it, see WIDContext::with_get_logout
+        routes.push(Route::new(Method::Get, "/logout", logout.clone()));
+        routes.push(Route::new(Method::Post, "/logout", logout));
+    }
+
+    if form_post {
+        routes.push(Route::new(Method::Post, "/callback", Callback));
+    }
+
+    routes
 }
 
 const STATE_LENGTH: usize = 20;
+/// Length of the HMAC following the random part of the state: URL-safe base64 of a SHA-256
+const STATE_MAC_LENGTH: usize = 43;
+
+/// Time users have to log in on WartID and come back to the callback
+const LOGIN_FLOW_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Length of the generated [request IDs][request_id]
+const REQUEST_ID_LENGTH: usize = 16;
+
+/// Lifetime of the cookies of [remembered][Login::remember_me] sessions, unless the context sets
+/// another one
+const REMEMBER_ME_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Maximum size of a `form_post` authorization response
+const FORM_POST_LIMIT: u64 = 8;
+
+/// Authorization request parameters set by [Login], which [extra ones][Login::with_extra_param]
+/// can't override
+const AUTHORIZE_PARAMS: &[&str] = &[
+    "response_type",
+    "client_id",
+    "redirect_uri",
+    "scope",
+    "state",
+    "response_mode",
+    "acr_values",
+    "audience",
+    "prompt",
+];
+
+/// Cookie holding a part of the session, kept across browser restarts if the session is
+/// [remembered][Login::remember_me] or the context
+/// [sets a max-age][WIDContext::with_cookie_max_age]
+fn session_cookie(
+    context: &WIDContext,
+    session: &WartIDSession,
+    suffix: &str,
+    value: String,
+) -> Cookie<'static> {
+    let max_age = match (session.remember_me, context.cookie_max_age) {
+        (_, Some(max_age)) => Some(max_age),
+        (true, None) => Some(REMEMBER_ME_MAX_AGE),
+        (false, None) => None,
+    };
+
+    let mut cookie = Cookie::build(context.cookie_name(suffix), value);
+    cookie = if context.partitioned_cookies {
+        cookie.same_site(SameSite::None).secure(true)
+    } else if context.strict_cookies {
+        cookie.same_site(SameSite::Strict)
+    } else {
+        cookie.same_site(SameSite::Lax)
+    };
+    if let Some(max_age) = max_age {
+        cookie = cookie.max_age(time::Duration::seconds(max_age.as_secs() as i64));
+    }
+
+    cookie.path(context.session_cookie_path.clone()).finish()
+}
+
+/// Cookie to remove a session cookie, which must have its path
+fn session_cookie_removal(context: &WIDContext, suffix: &str) -> Cookie<'static> {
+    Cookie::build(context.cookie_name(suffix), "")
+        .path(context.session_cookie_path.clone())
+        .finish()
+}
+
+/// Cookie to remove a cookie of the login flow, which must have its path
+fn flow_cookie_removal(context: &WIDContext, suffix: &str) -> Cookie<'static> {
+    Cookie::build(context.cookie_name(suffix), "")
+        .path(context.flow_cookie_path())
+        .finish()
+}
+
+/// Reads the session of the request from the [SessionStore][crate::store::SessionStore] if there
+/// is one, along with its id, or else from the cookies
+async fn load_session(
+    cookies: &CookieJar<'_>,
+    context: &WIDContext,
+) -> Result<(Option<String>, StoredSession), WartIDSessionError> {
+    if let Some(store) = &context.session_store {
+        let id = match cookies.get_private(&context.cookie_name("sid")) {
+            Some(cookie) => cookie.value().to_string(),
+            None => return Err(WartIDSessionError::MissingAuthorization),
+        };
+
+        return match store.get(&id).await {
+            Ok(Some(stored)) => Ok((Some(id), stored)),
+            Ok(None) => Err(WartIDSessionError::MissingAuthorization),
+            Err(err) => {
+                log::error!("[load_session] {}", err);
+                Err(WartIDSessionError::SessionStore)
+            }
+        };
+    }
+
+    if let Some(sealer) = &context.sealer {
+        let cookie = match cookies.get(&context.cookie_name("sealed")) {
+            Some(cookie) => cookie,
+            None => return Err(WartIDSessionError::MissingAuthorization),
+        };
+
+        return match sealer.open(cookie.value()) {
+            Ok(stored) => Ok((None, stored)),
+            Err(err @ SealError::Decoding(_)) => {
+                log::warn!("[load_session] {}", err);
+                Err(WartIDSessionError::Outdated)
+            }
+            Err(err) => {
+                log::error!("[load_session] {}", err);
+                Err(WartIDSessionError::SessionDecoding)
+            }
+        };
+    }
 
-fn rand_state() -> String {
+    let access_token = match cookies.get_private(&context.cookie_name("a")) {
+        Some(cookie) => cookie.value().to_string(),
+        None => return Err(WartIDSessionError::MissingAuthorization),
+    };
+
+    // Missing if WartID didn't issue a refresh token
+    let refresh_token = cookies
+        .get_private(&context.cookie_name("r"))
+        .map(|cookie| cookie.value().to_string());
+
+    let session = match cookies.get_private(&context.cookie_name("s")) {
+        Some(cookie) => match versioning::from_str(cookie.value()) {
+            Ok(x) => x,
+            Err(err) => {
+                log::warn!("[load_session] {}", err);
+                return Err(WartIDSessionError::Outdated);
+            }
+        },
+        None => return Err(WartIDSessionError::MissingUserinfo),
+    };
+
+    Ok((
+        None,
+        StoredSession {
+            session,
+            access_token,
+            refresh_token,
+        },
+    ))
+}
+
+/// Writes the session back to the store or the cookies. A new session id is generated if `id` is
+/// `None` and a store is used.
+async fn save_session(
+    cookies: &CookieJar<'_>,
+    context: &WIDContext,
+    id: Option<String>,
+    stored: &StoredSession,
+) {
+    if let Some(store) = &context.session_store {
+        let id = id.unwrap_or_else(|| {
+            let id = new_session_id();
+            cookies.add_private(session_cookie(context, &stored.session, "sid", id.clone()));
+            id
+        });
+
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("session_id", &id.as_str());
+
+        if let Err(err) = store.set(&id, stored).await {
+            log::error!("[save_session] {}", err);
+        }
+
+        return;
+    }
+
+    if let Some(sealer) = &context.sealer {
+        let mut cookie = session_cookie(context, &stored.session, "sealed", sealer.seal(stored));
+        cookie.set_http_only(true);
+        cookies.add(cookie);
+
+        return;
+    }
+
+    cookies.add_private(session_cookie(
+        context,
+        &stored.session,
+        "a",
+        stored.access_token.clone(),
+    ));
+
+    match &stored.refresh_token {
+        Some(refresh_token) => cookies.add_private(session_cookie(
+            context,
+            &stored.session,
+            "r",
+            refresh_token.clone(),
+        )),
+        None => cookies.remove_private(session_cookie_removal(context, "r")),
+    }
+
+    cookies.add_private(session_cookie(
+        context,
+        &stored.session,
+        "s",
+        versioning::to_string(&stored.session),
+    ));
+}
+
+fn clear_session(cookies: &CookieJar<'_>, context: &WIDContext) {
+    if let (Some(store), Some(cookie)) = (
+        &context.session_store,
+        cookies.get_private(&context.cookie_name("sid")),
+    ) {
+        let store = Arc::clone(store);
+        let id = cookie.value().to_string();
+        rocket::tokio::spawn(async move {
+            if let Err(err) = store.remove(&id).await {
+                log::error!("[clear_session] {}", err);
+            }
+        });
+
+        cookies.remove_private(session_cookie_removal(context, "sid"));
+    }
+
+    cookies.remove(session_cookie_removal(context, "sealed"));
+    cookies.remove_private(session_cookie_removal(context, "a"));
+    cookies.remove_private(session_cookie_removal(context, "r"));
+    cookies.remove_private(session_cookie_removal(context, "s"));
+}
+
+/// Selects the [WIDContext] of a request, for apps talking to several WartID instances (e.g. one
+/// per tenant)
+///
+/// Manage a `Box<dyn ContextResolver>` instead of a single [WIDContext] to use it, and give each
+/// context its own [tenant][WIDContext::with_tenant] so their cookies don't collide.
+pub trait ContextResolver: Send + Sync + 'static {
+    fn resolve<'r>(&'r self, request: &Request<'_>) -> Option<&'r WIDContext>;
+}
+
+fn find_context<'r>(request: &'r Request<'_>) -> Option<&'r WIDContext> {
+    let rocket = request.rocket();
+
+    match rocket.state::<Box<dyn ContextResolver>>() {
+        Some(resolver) => resolver.resolve(request),
+        None => rocket.state(),
+    }
+}
+
+/// Same as [find_context], for requests which must have a context: `None` is logged as the
+/// misconfiguration it is
+fn resolve_context<'r>(request: &'r Request<'_>) -> Option<&'r WIDContext> {
+    let context = find_context(request);
+
+    if context.is_none() {
+        log::error!(
+            "[resolve_context] no WIDContext for {}, call .manage(context) on the Rocket instance",
+            request.uri(),
+        );
+    }
+
+    context
+}
+
+fn forward_logged_out(request: &Request<'_>) -> bool {
+    find_context(request).map_or(false, |context| context.forward_logged_out)
+}
+
+/// Fairing adding the `Partitioned` attribute to the cookies of contexts with
+/// [partitioned cookies][WIDContext::with_partitioned_cookies]
+///
+/// The cookie builder can't set it, so it's appended to the `Set-Cookie` headers of the response.
+///
+/// ```no_run
+/// # let context: wartid_client::WIDContext = unimplemented!();
+/// rocket::ignite()
+///     .manage(context.with_partitioned_cookies())
+///     .attach(wartid_client::rocket::PartitionedCookies)
+///     .mount(
+///         "/oauth2/wartid",
+///         wartid_client::rocket::routes(wartid_client::handlers::Login::basic()),
+///     );
+/// ```
+pub struct PartitionedCookies;
+
+#[async_trait]
+impl Fairing for PartitionedCookies {
+    fn info(&self) -> Info {
+        Info {
+            name: "WartID partitioned cookies",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let context = match find_context(request) {
+            Some(context) if context.partitioned_cookies => context,
+            _ => return,
+        };
+
+        let prefix = context.cookie_name("");
+        let set_cookies = response
+            .headers()
+            .get("Set-Cookie")
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if !set_cookies.iter().any(|cookie| cookie.starts_with(&prefix)) {
+            return;
+        }
+
+        response.remove_header("Set-Cookie");
+        for cookie in set_cookies {
+            let partitioned = cookie.starts_with(&prefix)
+                && !cookie.to_ascii_lowercase().contains("; partitioned");
+            if partitioned {
+                response.adjoin_raw_header("Set-Cookie", format!("{}; Partitioned", cookie));
+            } else {
+                response.adjoin_raw_header("Set-Cookie", cookie);
+            }
+        }
+    }
+}
+
+fn rand_string(length: usize) -> String {
     use rand::{distributions::Alphanumeric, Rng};
 
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
-        .take(STATE_LENGTH)
+        .take(length)
         .map(char::from)
         .collect()
 }
 
+struct RequestId(String);
+
+/// Identifier tying the log lines of a request together, taken from its `X-Request-Id` header
+/// (e.g. set by a reverse proxy) when it looks sane, generated otherwise
+fn request_id<'r>(request: &'r Request<'_>) -> &'r str {
+    let id = request.local_cache(|| {
+        let header = request.headers().get_one("X-Request-Id").filter(|id| {
+            id.len() <= 64
+                && !id.is_empty()
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        });
+
+        RequestId(match header {
+            Some(id) => id.to_string(),
+            None => rand_string(REQUEST_ID_LENGTH),
+        })
+    });
+
+    &id.0
+}
+
 impl<'r> Responder<'r, 'static> for Login {
     fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
         let cookies = request.cookies();
-        let context: &WIDContext = request.rocket().state().expect("state isn't set");
+        let context = resolve_context(request).ok_or(Status::InternalServerError)?;
 
-        let mut state = rand_state();
-        if let Some(red) = self.redirect_to {
-            state.push_str(&red);
+        let request_id = request_id(request);
+        let redirect_to = match self.redirect_to {
+            Some(redirect_to) => sanitize_request_redirect(request, context, &redirect_to),
+            None => next_param(request, context),
         };
+        let state = sign_state(
+            context,
+            &rand_string(STATE_LENGTH),
+            redirect_to.as_deref().unwrap_or(""),
+        );
 
         #[derive(serde::Serialize)]
         struct Authorize<'a> {
@@ -76,33 +497,138 @@ impl<'r> Responder<'r, 'static> for Login {
             redirect_uri: &'a str,
             scope: &'a str,
             state: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            response_mode: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            acr_values: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            audience: Option<&'a str>,
+            #[serde(skip_serializing_if = "str::is_empty")]
+            prompt: &'a str,
             // nonce ?
         }
 
-        let authorize = match serde_urlencoded::to_string(Authorize {
-            response_type: "code",
+        let scope = Scope::join(self.requested_scopes);
+
+        // Extra `prompt` parameters are merged with the other values rather than ignored
+        let mut prompts = self.prompts.iter().map(String::as_str).collect::<Vec<_>>();
+        let extra_prompts = self
+            .extra_params
+            .iter()
+            .filter(|(key, _)| key == "prompt")
+            .flat_map(|(_, value)| value.split_whitespace());
+        for prompt in extra_prompts {
+            if !prompts.contains(&prompt) {
+                prompts.push(prompt);
+            }
+        }
+        let prompt = prompts.join(" ");
+
+        let mut authorize = match serde_urlencoded::to_string(Authorize {
+            response_type: RESPONSE_TYPE,
             client_id: &context.credentials.client_id,
             redirect_uri: &context.urls.callback,
-            scope: &self
-                .requested_scopes
-                .into_iter()
-                .collect::<Vec<_>>()
-                .join(" "),
+            scope: &scope,
             state: &state,
+            response_mode: if self.form_post {
+                Some("form_post")
+            } else {
+                None
+            },
+            acr_values: self.acr_values.as_deref(),
+            audience: self.audience.as_deref(),
+            prompt: &prompt,
         }) {
             Ok(x) => x,
             Err(_) => return Status::InternalServerError.respond_to(request),
         };
 
-        let redirect = format!("https://id.wp-corp.eu.org/oauth2/authorize?{}", authorize);
+        let extra_params = self
+            .extra_params
+            .iter()
+            .filter(|(key, _)| key != "prompt")
+            .filter(|(key, _)| {
+                let reserved = AUTHORIZE_PARAMS.contains(&key.as_str());
+                if reserved {
+                    log::warn!("[Login::respond_to] ignoring reserved parameter {}", key);
+                }
+                !reserved
+            })
+            .collect::<Vec<_>>();
+        if !extra_params.is_empty() {
+            match serde_urlencoded::to_string(&extra_params) {
+                Ok(extra) => {
+                    authorize.push('&');
+                    authorize.push_str(&extra);
+                }
+                Err(_) => return Status::InternalServerError.respond_to(request),
+            }
+        }
+
+        let redirect = format!("{}?{}", context.client.authorize_url(), authorize);
+
+        context.metrics.login_initiated();
+        log::debug!("[Login::respond_to] [{}] login flow started", request_id);
+
+        // The form_post response is a cross-site POST, which doesn't carry `Lax` cookies
+        let cross_site = self.form_post || context.partitioned_cookies;
+        let same_site = if cross_site {
+            SameSite::None
+        } else {
+            SameSite::Lax
+        };
+        let flow_path = context.flow_cookie_path();
+        // The expiry is checked by the callback, the cookies just have to outlive it
+        let flow_max_age =
+            time::Duration::seconds((LOGIN_FLOW_TTL + context.login_flow_leeway).as_secs() as i64);
 
+        // Prefixed by the time the flow started, for the callback to check its expiry
         cookies.add_private(
-            Cookie::build("wartid_auth_state", state)
-                .max_age(time::Duration::minutes(10))
-                .same_site(SameSite::Lax)
+            Cookie::build(
+                context.cookie_name("auth_state"),
+                format!("{}.{}", Utc::now().timestamp(), state),
+            )
+            .max_age(flow_max_age)
+            .same_site(same_site)
+            .secure(cross_site)
+            .path(flow_path.clone())
+            .finish(),
+        );
+
+        if self.remember_me {
+            cookies.add_private(
+                Cookie::build(context.cookie_name("auth_remember"), "1")
+                    .max_age(flow_max_age)
+                    .same_site(same_site)
+                    .secure(cross_site)
+                    .path(flow_path.clone())
+                    .finish(),
+            );
+        }
+
+        // Kept to tell which scopes weren't granted once back on the callback
+        cookies.add_private(
+            Cookie::build(context.cookie_name("auth_scopes"), scope)
+                .max_age(flow_max_age)
+                .same_site(same_site)
+                .secure(cross_site)
+                .path(flow_path.clone())
                 .finish(),
         );
 
+        // Ties the callback's log lines to this request
+        cookies.add_private(
+            Cookie::build(
+                context.cookie_name("auth_request_id"),
+                request_id.to_string(),
+            )
+            .max_age(flow_max_age)
+            .same_site(same_site)
+            .secure(cross_site)
+            .path(flow_path)
+            .finish(),
+        );
+
         Redirect::temporary(redirect).respond_to(request)
     }
 }
@@ -114,14 +640,402 @@ impl Handler for Login {
         request: &'r Request<'_>,
         _: Data,
     ) -> rocket::handler::Outcome<'r> {
+        if self.skip_if_logged_in && !self.has_prompt() {
+            if let Outcome::Success(_) = request.guard::<&WartIDSession>().await {
+                // The guard succeeding implies the context is there
+                if let Some(context) = find_context(request) {
+                    let path = match &self.redirect_to {
+                        Some(redirect_to) => {
+                            sanitize_request_redirect(request, context, redirect_to)
+                        }
+                        None => next_param(request, context),
+                    };
+                    log::debug!(
+                        "[Login::handle] [{}] already logged in, skipping WartID",
+                        request_id(request)
+                    );
+
+                    return rocket::handler::Outcome::from(
+                        request,
+                        Redirect::temporary(
+                            path.unwrap_or_else(|| context.post_login_redirect.clone()),
+                        ),
+                    );
+                }
+            }
+        }
+
         rocket::handler::Outcome::from(request, self.clone())
     }
 }
 
+/// `response_type` requested by [Login]: the authorization code flow, whose response
+/// [CallbackParams] reads
+const RESPONSE_TYPE: &str = "code";
+
+/// Authorization response of the [RESPONSE_TYPE] flow
 #[derive(Debug, serde::Deserialize)]
 struct CallbackParams<'a> {
     code: &'a str,
     state: &'a str,
+    /// Only returned by the implicit and hybrid flows, which weren't requested
+    id_token: Option<String>,
+    access_token: Option<String>,
+}
+
+fn error_status(err: &WartIDError) -> Status {
+    match err {
+        WartIDError::InvalidCallback | WartIDError::MissingState | WartIDError::InvalidGrant => {
+            Status::BadRequest
+        }
+        WartIDError::StateMismatch
+        | WartIDError::InvalidToken
+        | WartIDError::Unauthorized { .. } => Status::Unauthorized,
+        WartIDError::UnverifiedEmail | WartIDError::AccessDenied => Status::Forbidden,
+        WartIDError::TokenEndpoint(_)
+        | WartIDError::Request(_)
+        | WartIDError::Decoding(_)
+        | WartIDError::UnsupportedTokenType(_)
+        | WartIDError::LoginHook(_) => Status::InternalServerError,
+        WartIDError::RateLimited { .. } => Status::ServiceUnavailable,
+    }
+}
+
+/// Where [callback] sends the user
+enum CallbackOutcome {
+    /// The user just logged in
+    LoggedIn(String),
+    /// The callback was replayed (e.g. with the back button) by a user already logged in, who
+    /// didn't log in again
+    Replayed(String),
+}
+
+/// Exchanges the authorization code and sets up the session
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        skip(request, context, body),
+        fields(
+            path = %request.uri().path(),
+            session_id = tracing::field::Empty,
+            error = tracing::field::Empty
+        )
+    )
+)]
+async fn callback(
+    request: &Request<'_>,
+    context: &WIDContext,
+    body: Option<&str>,
+    request_id: &str,
+) -> Result<CallbackOutcome, WartIDError> {
+    let cookies = request.cookies();
+    let query: Option<&str> = match body {
+        Some(body) => Some(body),
+        None => request.uri().query().map(AsRef::as_ref),
+    };
+    let params: CallbackParams =
+        match serde_urlencoded::from_str(query.as_deref().unwrap_or_default()) {
+            Ok(params) => params,
+            Err(_err) => {
+                return Err(WartIDError::InvalidCallback);
+            }
+        };
+
+    // Tokens in the front channel mean WartID answered another flow than the requested one
+    if params.id_token.is_some() || params.access_token.is_some() {
+        log::error!(
+            "[Callback::handle] [{}] tokens in the authorization response, expected \
+            response_type={}",
+            request_id,
+            RESPONSE_TYPE,
+        );
+        return Err(WartIDError::InvalidCallback);
+    }
+
+    // State verification
+    if let Some(expected_state_cookie) = cookies.get_private(&context.cookie_name("auth_state")) {
+        let (issued_at, expected_state) = match expected_state_cookie
+            .value()
+            .split_once('.')
+            .and_then(|(issued_at, state)| Some((issued_at.parse::<i64>().ok()?, state)))
+        {
+            Some(parsed) => parsed,
+            // Set by an older version of the app, the flow is restarted
+            None => {
+                cookies.remove_private(flow_cookie_removal(context, "auth_state"));
+                return Err(WartIDError::MissingState);
+            }
+        };
+
+        if expected_state != params.state {
+            log::error!(
+                "[Callback::handle] [{}] state mismatch, possible CSRF attempt",
+                request_id
+            );
+            return Err(WartIDError::StateMismatch);
+        }
+
+        // Equal but not issued by us, e.g. both fixated by someone able to set cookies
+        if !state_signed(context, params.state) {
+            log::error!(
+                "[Callback::handle] [{}] state not signed by this app, possible CSRF attempt",
+                request_id
+            );
+            return Err(WartIDError::StateMismatch);
+        }
+
+        cookies.remove_private(flow_cookie_removal(context, "auth_state"));
+
+        let elapsed = Utc::now().timestamp() - issued_at;
+        if elapsed > (LOGIN_FLOW_TTL + context.login_flow_leeway).as_secs() as i64 {
+            log::warn!(
+                "[Callback::handle] [{}] login flow expired after {} minutes",
+                request_id,
+                elapsed / 60
+            );
+            return Err(WartIDError::MissingState);
+        }
+    } else {
+        // The callback was replayed (e.g. with the back button) after the flow completed and
+        // removed the state cookie: users still logged in are sent where the flow led them
+        if state_signed(context, params.state)
+            && read_session(cookies, context, request_id).await.is_ok()
+        {
+            log::warn!(
+                "[Callback::handle] [{}] callback replayed by a logged in user, ignoring",
+                request_id
+            );
+            return Ok(CallbackOutcome::Replayed(
+                state_redirection(params.state)
+                    .unwrap_or_else(|| context.post_login_redirect.clone()),
+            ));
+        }
+
+        return Err(WartIDError::MissingState);
+    }
+
+    let requested_scopes = cookies
+        .get_private(&context.cookie_name("auth_scopes"))
+        .map(|cookie| cookie.value().to_string())
+        .unwrap_or_default();
+    cookies.remove_private(flow_cookie_removal(context, "auth_scopes"));
+
+    let remember_me = cookies
+        .get_private(&context.cookie_name("auth_remember"))
+        .is_some();
+    cookies.remove_private(flow_cookie_removal(context, "auth_remember"));
+
+    let client = &context.client;
+    let token_response = client.request_token(context, params.code).await;
+
+    let token = match token_response {
+        Ok(token) => token,
+        // The callback was replayed (e.g. with the back button) while the state cookie was still
+        // there, e.g. because of a flow started in another tab: users still logged in are sent
+        // where the flow led them
+        Err(WartIDError::InvalidGrant)
+            if read_session(cookies, context, request_id).await.is_ok() =>
+        {
+            log::warn!(
+                "[Callback::handle] [{}] authorization code already used by a logged in user, \
+                ignoring",
+                request_id
+            );
+            return Ok(CallbackOutcome::Replayed(
+                state_redirection(params.state)
+                    .unwrap_or_else(|| context.post_login_redirect.clone()),
+            ));
+        }
+        Err(err) => {
+            #[cfg(feature = "tracing")]
+            tracing::Span::current().record("error", &tracing::field::display(&err));
+
+            return Err(err);
+        }
+    };
+
+    // The token was just issued, so there's no point checking its expiry
+    let userinfo = match context.identity_source {
+        IdentitySource::Userinfo => {
+            client
+                .request_userinfo_with_token(&token.access_token)
+                .await
+        }
+        IdentitySource::IdToken => match &token.id_token {
+            // at_hash is only checked once the ID token is verified, anyone can forge it otherwise.
+            // With the userinfo source, the access token is vouched for by WartID instead.
+            Some(id_token) => {
+                jwt::verify_id_token(client, id_token, &context.credentials.client_id)
+                    .await
+                    .and_then(|userinfo| {
+                        jwt::verify_at_hash(id_token, &token.access_token)?;
+                        Ok(userinfo)
+                    })
+            }
+            None => {
+                log::error!(
+                    "[Callback::handle] [{}] no ID token to read the identity from",
+                    request_id
+                );
+                Err(WartIDError::InvalidToken)
+            }
+        },
+    };
+
+    let userinfo = match userinfo {
+        Ok(userinfo) => userinfo,
+        Err(err) => {
+            #[cfg(feature = "tracing")]
+            tracing::Span::current().record("error", &tracing::field::display(&err));
+
+            return Err(err);
+        }
+    };
+
+    let mut session: WartIDSession = userinfo.clone().into();
+    session.id_token = token.id_token.clone();
+    if let Some(id_token) = &token.id_token {
+        let authentication = jwt::authentication_context(id_token);
+        session.acr = authentication.acr;
+        session.amr = authentication.amr;
+
+        #[cfg(feature = "raw-claims")]
+        for (name, value) in jwt::claims(id_token) {
+            session.raw_claims.entry(name).or_insert(value);
+        }
+    }
+    session.token_expires_at = token.expires_at();
+    // WartID omits the granted scopes when they're the requested ones
+    session.scopes = token
+        .scope
+        .clone()
+        .unwrap_or_else(|| requested_scopes.clone());
+    session.requested_scopes = requested_scopes;
+    session.remember_me = remember_me;
+
+    let missing_scopes = session.missing_scopes();
+    if !missing_scopes.is_empty() {
+        scope::warn_not_granted(&missing_scopes);
+        log::debug!(
+            "[Callback::handle] [{}] scopes not granted to {}: {}",
+            request_id,
+            session.id,
+            missing_scopes.join(" "),
+        );
+    }
+
+    if context.require_verified_email && session.email.is_some() && !session.email_verified {
+        return Err(WartIDError::UnverifiedEmail);
+    }
+
+    if !context.email_domain_allowed(&session) {
+        log::warn!(
+            "[Callback::handle] [{}] login of {} refused, no verified email in an allowed domain",
+            request_id,
+            session.id,
+        );
+        return Err(WartIDError::AccessDenied);
+    }
+
+    if let Some(authorizer) = &context.user_authorizer {
+        if !authorizer.authorize_user(&userinfo).await {
+            log::warn!(
+                "[Callback::handle] [{}] login of {} refused by the user authorizer",
+                request_id,
+                session.id,
+            );
+            return Err(WartIDError::AccessDenied);
+        }
+    }
+
+    if let Some(hook) = &context.login_hook {
+        if let Err(err) = hook.on_login(&token, &userinfo).await {
+            #[cfg(feature = "tracing")]
+            tracing::Span::current().record("error", &tracing::field::display(&err));
+
+            return Err(WartIDError::LoginHook(err));
+        }
+    }
+
+    let stored = StoredSession {
+        session,
+        access_token: token.access_token.clone(),
+        refresh_token: token.refresh_token.clone(),
+    };
+
+    save_session(cookies, context, None, &stored).await;
+
+    Ok(CallbackOutcome::LoggedIn(
+        state_redirection(params.state).unwrap_or_else(|| context.post_login_redirect.clone()),
+    ))
+}
+
+/// Page sending the user to `path` with a same-site navigation, so that `SameSite=Strict`
+/// cookies are sent along. A redirection from the callback would still count as the cross-site
+/// navigation coming from WartID.
+fn same_site_bridge(path: &str) -> Html<String> {
+    let path = path
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+
+    Html(format!(
+        "<!DOCTYPE html><meta http-equiv=\"refresh\" content=\"0; url={0}\">\
+        <a href=\"{0}\">Continue</a>",
+        path
+    ))
+}
+
+/// Path to redirect to once logged in, carried after the random part of the state and its MAC
+fn state_redirection(state: &str) -> Option<String> {
+    state
+        .get(STATE_LENGTH + STATE_MAC_LENGTH..)
+        .and_then(sanitize_redirect)
+}
+
+/// HMAC of the random part of the state and the redirection, keyed by the context's
+/// [state key][WIDContext::with_state_key]
+///
+/// It proves the state was issued by this app: someone able to set cookies on the domain can't
+/// fixate a state of their own in both the cookie and the callback URL, nor tamper with the
+/// redirection it carries. It isn't bound to the browser though, so a state the app issued to
+/// someone else is still signed: only its equality with the state cookie ties it to the user.
+fn state_mac(context: &WIDContext, random: &str, redirect: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(&context.state_key).expect("HMAC takes keys of any length");
+    mac.update(random.as_bytes());
+    mac.update(redirect.as_bytes());
+    mac
+}
+
+/// Builds the state of a login flow: the random part, its MAC, then the redirection
+fn sign_state(context: &WIDContext, random: &str, redirect: &str) -> String {
+    let tag = state_mac(context, random, redirect).finalize().into_bytes();
+
+    format!(
+        "{}{}{}",
+        random,
+        base64::encode_config(tag, base64::URL_SAFE_NO_PAD),
+        redirect
+    )
+}
+
+/// Whether the state was built by [sign_state] with the same state key
+fn state_signed(context: &WIDContext, state: &str) -> bool {
+    let (random, tag, redirect) = match (
+        state.get(..STATE_LENGTH),
+        state.get(STATE_LENGTH..STATE_LENGTH + STATE_MAC_LENGTH),
+        state.get(STATE_LENGTH + STATE_MAC_LENGTH..),
+    ) {
+        (Some(random), Some(tag), Some(redirect)) => (random, tag, redirect),
+        _ => return false,
+    };
+
+    match base64::decode_config(tag, base64::URL_SAFE_NO_PAD) {
+        Ok(tag) => state_mac(context, random, redirect).verify(&tag).is_ok(),
+        Err(_) => false,
+    }
 }
 
 #[rocket::async_trait]
@@ -129,102 +1043,379 @@ impl Handler for Callback {
     async fn handle<'r, 's: 'r>(
         &'s self,
         request: &'r Request<'_>,
-        _: Data,
+        data: Data,
     ) -> rocket::handler::Outcome<'r> {
+        use rocket::data::ToByteUnit;
         use rocket::handler::Outcome;
+        use rocket::tokio::io::AsyncReadExt;
+
+        let context = match resolve_context(request) {
+            Some(context) => context,
+            None => return Outcome::Failure(Status::InternalServerError),
+        };
 
+        // The ID of the request which started the flow, so its log lines can be followed
         let cookies = request.cookies();
-        let context: &WIDContext = request.rocket().state().expect("state isn't set");
-        let query: Option<&str> = request.uri().query().map(AsRef::as_ref);
-        let params: CallbackParams =
-            match serde_urlencoded::from_str(query.as_deref().unwrap_or_default()) {
-                Ok(params) => params,
-                Err(_err) => {
-                    return Outcome::Failure(Status::BadRequest);
-                }
-            };
+        let request_id = match cookies.get_private(&context.cookie_name("auth_request_id")) {
+            Some(cookie) => cookie.value().to_string(),
+            None => request_id(request).to_string(),
+        };
+        cookies.remove_private(flow_cookie_removal(context, "auth_request_id"));
 
-        // State verification
-        if let Some(expected_state_cookie) = cookies.get_private("wartid_auth_state") {
-            if expected_state_cookie.value() != params.state {
-                return Outcome::Failure(Status::Unauthorized);
+        // form_post authorization response
+        let body = if request.method() == Method::Post {
+            let mut body = String::new();
+            let mut stream = data.open(FORM_POST_LIMIT.kibibytes());
+            if let Err(err) = stream.read_to_string(&mut body).await {
+                log::error!(
+                    "[Callback::handle] [{}] error reading the form_post body: {}",
+                    request_id,
+                    err
+                );
+                return Outcome::Failure(Status::BadRequest);
             }
 
-            cookies.remove_private(Cookie::named("wartid_auth_state"));
+            Some(body)
         } else {
-            return Outcome::Failure(Status::BadRequest);
-        }
-
-        let client = Client::default(); // TODO optimise
-        let token_response = client.request_token(context, params.code).await;
-
-        let token = match token_response {
-            Ok(token) => token,
-            Err(err) => {
-                error!("Request error: {:?}", err);
-                return Outcome::from(request, Status::InternalServerError);
-            }
+            None
         };
 
-        if let Some(refresh) = token.refresh_token {
-            let mut authorization = Authorization::new(&token.access_token, &refresh);
+        match callback(request, context, body.as_deref(), &request_id).await {
+            Ok(outcome) => {
+                // Replayed callbacks don't count as logins
+                let path = match outcome {
+                    CallbackOutcome::LoggedIn(path) => {
+                        context.metrics.callback_succeeded();
+                        path
+                    }
+                    CallbackOutcome::Replayed(path) => path,
+                };
 
-            let userinfo = match client.request_userinfo(context, &mut authorization).await {
-                Ok(userinfo) => userinfo,
-                Err(err) => {
-                    log::error!("[Callback::handle] {}", err);
-                    return Outcome::Failure(Status::BadRequest);
+                if context.strict_cookies && !context.partitioned_cookies {
+                    Outcome::from(request, same_site_bridge(&path))
+                } else {
+                    Outcome::from(request, Redirect::temporary(path))
                 }
-            };
-
-            let session: WartIDSession = userinfo.into();
+            }
+            // The state cookie expired, or the callback URL was bookmarked: the flow is restarted
+            // rather than dead-ending on an error
+            Err(WartIDError::MissingState) => {
+                context.metrics.callback_failed();
+                log::warn!(
+                    "[Callback::handle] [{}] login flow expired, restarting it",
+                    request_id
+                );
 
-            cookies.add_private(
-                Cookie::build("wartid_s", serde_json::to_string(&session).unwrap())
-                    .same_site(SameSite::Lax)
-                    .finish(),
-            );
+                // Read from the body for form_post responses, like the callback does
+                let params = match body.as_deref() {
+                    Some(body) => Some(body),
+                    None => request.uri().query().map(AsRef::as_ref),
+                };
+                let next = params
+                    .and_then(|params| serde_urlencoded::from_str::<CallbackParams>(params).ok())
+                    .and_then(|params| state_redirection(params.state));
 
-            cookies.add_private(
-                Cookie::build("wartid_r", refresh)
-                    .same_site(SameSite::Lax)
-                    .finish(),
-            );
-        }
+                let redirect = match next {
+                    Some(next) => format!(
+                        "{}?{}",
+                        context.urls.login,
+                        serde_urlencoded::to_string(&[("next", next)]).unwrap(),
+                    ),
+                    None => context.urls.login.clone(),
+                };
 
-        cookies.add_private(
-            Cookie::build("wartid_a", token.access_token)
-                .same_site(SameSite::Lax)
-                .finish(),
-        );
+                Outcome::from(request, Redirect::to(redirect))
+            }
+            Err(err) => {
+                context.metrics.callback_failed();
+                log::error!("[Callback::handle] [{}] {}", request_id, err);
 
-        rocket::handler::Outcome::from(request, Redirect::temporary("/"))
+                match &context.callback_error_redirect {
+                    Some(url) => {
+                        let separator = if url.contains('?') { '&' } else { '?' };
+                        let redirect = format!(
+                            "{}{}{}",
+                            url,
+                            separator,
+                            serde_urlencoded::to_string(&[
+                                ("error", err.code()),
+                                ("request_id", request_id.as_str()),
+                            ])
+                            .unwrap(),
+                        );
+                        Outcome::from(request, Redirect::to(redirect))
+                    }
+                    None => Outcome::Failure(error_status(&err)),
+                }
+            }
+        }
     }
 }
 
 impl<'r, 'o: 'r> Responder<'r, 'o> for Logout {
     fn respond_to(self, request: &Request<'_>) -> rocket::response::Result<'o> {
         let cookies = request.cookies();
+        let context = resolve_context(request).ok_or(Status::InternalServerError)?;
 
-        cookies.remove_private(Cookie::named("wartid_a"));
-        cookies.remove_private(Cookie::named("wartid_r"));
-        cookies.remove_private(Cookie::named("wartid_data"));
+        clear_session(cookies, context);
 
-        Redirect::to(self.0.unwrap_or("/")).respond_to(request)
+        let redirect = self
+            .0
+            .or_else(|| next_param(request, context))
+            .unwrap_or_else(|| "/".into());
+
+        Redirect::to(redirect).respond_to(request)
     }
 }
 
+/// Public origin of the request, e.g. `https://example.com`, read from the forwarded headers if
+/// [trusted][WIDContext::with_trusted_forwarded_headers]
+fn request_origin(request: &Request<'_>, context: &WIDContext) -> Option<String> {
+    // Proxies chaining these headers append their values, the first one is the client's
+    let forwarded = |name: &str| {
+        if context.trust_forwarded_headers {
+            request
+                .headers()
+                .get_one(name)
+                .and_then(|value| value.split(',').next())
+                .map(str::trim)
+        } else {
+            None
+        }
+    };
+
+    let scheme = forwarded("X-Forwarded-Proto").or_else(|| {
+        context
+            .urls
+            .callback
+            .split_once("://")
+            .map(|(scheme, _)| scheme)
+    })?;
+    let host = forwarded("X-Forwarded-Host").or_else(|| request.headers().get_one("Host"))?;
+
+    Some(format!("{}://{}", scheme, host))
+}
+
+/// Same as [sanitize_redirect], also accepting absolute URLs on the request's own origin, which
+/// are turned into paths
+fn sanitize_request_redirect(
+    request: &Request<'_>,
+    context: &WIDContext,
+    target: &str,
+) -> Option<String> {
+    let url = match url::Url::parse(target) {
+        Ok(url) => url,
+        Err(_) => return sanitize_redirect(target),
+    };
+
+    let origin = url::Url::parse(&request_origin(request, context)?).ok()?;
+    if url.origin() != origin.origin() {
+        return None;
+    }
+
+    sanitize_redirect(&url[url::Position::BeforePath..])
+}
+
+/// Reads the `next` query parameter of the request, if it's on the same origin
+fn next_param(request: &Request<'_>, context: &WIDContext) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct NextParams {
+        next: Option<String>,
+    }
+
+    let query: &str = request.uri().query()?.as_ref();
+    let next = serde_urlencoded::from_str::<NextParams>(query).ok()?.next?;
+
+    sanitize_request_redirect(request, context, &next)
+}
+
 #[rocket::async_trait]
 impl Handler for Logout {
     async fn handle<'r, 's: 'r>(
         &'s self,
         request: &'r Request<'_>,
-        _: Data,
+        data: Data,
     ) -> rocket::handler::Outcome<'r> {
+        let context = match resolve_context(request) {
+            Some(context) => context,
+            None => return rocket::handler::Outcome::Failure(Status::InternalServerError),
+        };
+
+        if request.method() == Method::Get && !context.get_logout {
+            return rocket::handler::Outcome::Forward(data);
+        }
+
+        // Their identity may have changed on WartID by the time they log in again
+        if context.client.caches_userinfo() {
+            if let Ok((_, stored)) = load_session(request.cookies(), context).await {
+                context.client.invalidate_userinfo(&stored.session.id);
+            }
+        }
+
         rocket::handler::Outcome::from(request, self.clone())
     }
 }
 
+/// Reads the session from the cookies, refreshing its tokens or userinfo if needed, for code
+/// which can't use the [session guards][WartIDSession], e.g. GraphQL resolvers or WebSocket
+/// handlers
+///
+/// Like the guards, it updates the cookies of the jar when the session changes, which only reach
+/// the browser if the response wasn't sent yet. Bearer tokens aren't looked at.
+pub async fn session_from_cookies(
+    cookies: &CookieJar<'_>,
+    context: &WIDContext,
+) -> Result<WartIDSession, WartIDSessionError> {
+    read_session(cookies, context, &rand_string(REQUEST_ID_LENGTH))
+        .await
+        .map(|(session, _)| session)
+}
+
+/// See [session_from_cookies], `request_id` tying its log lines to the request. Also returns
+/// whether the tokens were refreshed.
+async fn read_session(
+    cookies: &CookieJar<'_>,
+    context: &WIDContext,
+    request_id: &str,
+) -> Result<(WartIDSession, bool), WartIDSessionError> {
+    let (id, mut stored) = match load_session(cookies, context).await {
+        // Unreadable cookies would otherwise stick around, failing every request
+        Err(err @ WartIDSessionError::Outdated)
+        | Err(err @ WartIDSessionError::SessionDecoding) => {
+            clear_session(cookies, context);
+            return Err(err);
+        }
+        result => result?,
+    };
+
+    let now = Utc::now().timestamp();
+    if context.session_timed_out(&stored.session, now) {
+        clear_session(cookies, context);
+        return Err(WartIDSessionError::Expired);
+    }
+
+    let mut dirty = false;
+
+    // Saved before login timestamps were tracked: the absolute lifetime starts now rather than
+    // logging the user out
+    if stored.session.issued_at == 0 {
+        stored.session.issued_at = now;
+        dirty = true;
+    }
+
+    let mut authorization =
+        Authorization::new(&stored.access_token, stored.refresh_token.as_deref())
+            .with_expiry(stored.session.token_expires_at);
+
+    // Sessions without a refresh token end with their access token
+    if stored.refresh_token.is_none() && authorization.expired(&context.client) {
+        clear_session(cookies, context);
+        return Err(WartIDSessionError::Expired);
+    }
+
+    if let Err(err) = authorization.try_refresh(context, &context.client).await {
+        log::error!("[read_session] [{}] error refreshing: {}", request_id, err);
+
+        return Err(match err {
+            WartIDError::InvalidGrant => {
+                clear_session(cookies, context);
+                WartIDSessionError::RefreshRejected
+            }
+            WartIDError::Request(_) | WartIDError::RateLimited { .. } => {
+                WartIDSessionError::Network
+            }
+            _ => WartIDSessionError::Refreshing,
+        });
+    }
+
+    let refreshed = authorization.take_updated();
+
+    let mut tokens_refreshed = false;
+
+    if let Some((access_token, refresh_token, expires_at)) = refreshed {
+        // A token for someone else means the provider is either buggy or compromised
+        if let Some(subject) = jwt::subject(&access_token) {
+            if subject != stored.session.id {
+                log::error!(
+                    "[read_session] [{}] refreshed token subject {} doesn't match the session's {}",
+                    request_id,
+                    subject,
+                    stored.session.id,
+                );
+                clear_session(cookies, context);
+                return Err(WartIDSessionError::SubjectMismatch);
+            }
+        }
+
+        stored.access_token = access_token;
+        stored.refresh_token = Some(refresh_token);
+        stored.session.token_expires_at = expires_at;
+        tokens_refreshed = true;
+        dirty = true;
+    }
+
+    if let Some(ttl) = context.userinfo_ttl {
+        if now.saturating_sub(stored.session.userinfo_fetched_at) > ttl.as_secs() as i64 {
+            let mut authorization =
+                Authorization::new(&stored.access_token, stored.refresh_token.as_deref())
+                    .with_expiry(stored.session.token_expires_at);
+
+            let userinfo = context
+                .client
+                .userinfo_cached(context, &mut authorization, &stored.session.id)
+                .await;
+            let refreshed = authorization.take_updated();
+
+            if let Some((access_token, refresh_token, expires_at)) = refreshed {
+                stored.access_token = access_token;
+                stored.refresh_token = Some(refresh_token);
+                stored.session.token_expires_at = expires_at;
+                tokens_refreshed = true;
+                dirty = true;
+            }
+
+            match userinfo {
+                Ok(userinfo) => {
+                    if !stored.session.update_userinfo(userinfo, now) {
+                        log::error!(
+                            "[read_session] [{}] userinfo subject doesn't match the session's {}",
+                            request_id,
+                            stored.session.id,
+                        );
+                        clear_session(cookies, context);
+                        return Err(WartIDSessionError::SubjectMismatch);
+                    }
+
+                    dirty = true;
+                }
+                // Even fresh tokens are rejected, the user has to log in again
+                Err(err @ WartIDError::Unauthorized { .. }) => {
+                    log::warn!("[read_session] [{}] {}", request_id, err);
+                    clear_session(cookies, context);
+                    return Err(WartIDSessionError::Revoked);
+                }
+                // Stale data is better than no session at all
+                Err(err) => log::warn!(
+                    "[read_session] [{}] error refreshing userinfo: {}",
+                    request_id,
+                    err
+                ),
+            }
+        }
+    }
+
+    if context.idle_timeout.is_some() {
+        stored.session.last_seen = now;
+        dirty = true;
+    }
+
+    if dirty {
+        save_session(cookies, context, id, &stored).await;
+    }
+
+    Ok((stored.session, tokens_refreshed))
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for &'r WartIDSession {
     type Error = WartIDSessionError;
@@ -233,69 +1424,128 @@ impl<'r> FromRequest<'r> for &'r WartIDSession {
         let result = request
             .local_cache_async::<Result<WartIDSession, Self::Error>, _>(async {
                 let cookies = request.cookies();
-                let context: &WIDContext = request.rocket().state().expect("state isn't set");
-
-                let c_a = cookies.get_private("wartid_a");
-                let c_a_val = match &c_a {
-                    Some(cookie) => cookie.value(),
-                    None => return Err(WartIDSessionError::MissingAuthorization),
-                };
+                let context = resolve_context(request).ok_or(WartIDSessionError::MissingContext)?;
 
-                let c_r = cookies.get_private("wartid_r");
-                let c_r_val = match &c_r {
-                    Some(cookie) => cookie.value(),
-                    None => return Err(WartIDSessionError::MissingRefresh),
+                let bearer = match context.bearer_tokens {
+                    BearerTokens::Ignored => None,
+                    _ => bearer_token(request.headers().get_one("Authorization")),
                 };
 
-                let mut authorization = Authorization::new(c_a_val, c_r_val);
-                if let Err(err) = authorization.try_refresh(context, &Client::default()).await {
-                    log::error!("[WartIDSession::from_request] error refreshing: {}", err);
-                    return Err(WartIDSessionError::Refreshing);
+                if let (Some(token), BearerTokens::Preferred) = (bearer, context.bearer_tokens) {
+                    return authenticate_bearer(context, token).await;
                 }
 
-                if let Authorization::Dirty {
-                    access_token,
-                    refresh_token,
-                } = authorization
-                {
-                    cookies.add_private(
-                        Cookie::build("wartid_r", refresh_token)
-                            .same_site(SameSite::Lax)
-                            .finish(),
-                    );
-
-                    cookies.add_private(
-                        Cookie::build("wartid_a", access_token)
-                            .same_site(SameSite::Lax)
-                            .finish(),
-                    );
+                match (
+                    read_session(cookies, context, request_id(request)).await,
+                    bearer,
+                ) {
+                    (Err(WartIDSessionError::MissingAuthorization), Some(token)) => {
+                        authenticate_bearer(context, token).await
+                    }
+                    (Ok((session, tokens_refreshed)), _) => {
+                        request.local_cache(|| TokensRefreshed(tokens_refreshed));
+                        Ok(session)
+                    }
+                    (Err(err), _) => Err(err),
                 }
-
-                let c_s = cookies.get_private("wartid_s");
-                let c_s_val = match &c_s {
-                    Some(cookie) => cookie.value(),
-                    None => return Err(WartIDSessionError::MissingUserinfo),
-                };
-
-                let session = match serde_json::from_str::<WartIDSession>(c_s_val) {
-                    Ok(x) => x,
-                    Err(_) => return Err(WartIDSessionError::SessionDecoding),
-                };
-
-                Ok(session)
             })
             .await;
 
         match result {
             Ok(session) => Outcome::Success(session),
+            Err(err) if err.is_logged_out() && forward_logged_out(request) => Outcome::Forward(()),
+            // Not the user's fault, they shouldn't be asked to log in again
+            Err(WartIDSessionError::Network) => {
+                Outcome::Failure((Status::ServiceUnavailable, WartIDSessionError::Network))
+            }
+            Err(WartIDSessionError::MissingContext) => Outcome::Failure((
+                Status::InternalServerError,
+                WartIDSessionError::MissingContext,
+            )),
             Err(err) => Outcome::Failure((Status::Unauthorized, *err)),
         }
     }
 }
 
+/// Whether the session guard refreshed the tokens of the session during this request, e.g. to
+/// update state derived from them
+///
+/// The session guard is run if it wasn't already, so this guard can be declared in any order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TokensRefreshed(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TokensRefreshed {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let _ = request.guard::<&WartIDSession>().await;
+
+        Outcome::Success(*request.local_cache(|| TokensRefreshed(false)))
+    }
+}
+
+/// Owned session, cloned out of the request's cache, for handlers moving it into spawned tasks or
+/// returning it
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WartIDSession {
+    type Error = WartIDSessionError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        request
+            .guard::<&WartIDSession>()
+            .await
+            .map(WartIDSession::clone)
+    }
+}
+
 impl<'a> WartIDSessionOrRedirect<'a> {
+    /// Redirects to the [login url][crate::WIDContextUrls::login] of the context
     pub fn rocket(self) -> Result<&'a WartIDSession, Redirect> {
-        self.0.ok_or_else(|| Redirect::to("/oauth2/wartid/login"))
+        let login = self.1;
+        self.rocket_to(login)
+    }
+
+    /// Same as [rocket][WartIDSessionOrRedirect::rocket], redirecting to the given path instead of
+    /// the context's login url
+    pub fn rocket_to(self, login_path: &str) -> Result<&'a WartIDSession, Redirect> {
+        self.0.ok_or_else(|| Redirect::to(login_path.to_string()))
+    }
+
+    /// Same as [rocket][WartIDSessionOrRedirect::rocket], also rejecting sessions which weren't
+    /// granted the given scope, if any. Routes wrapped by
+    /// [wartid_protected][crate::wartid_protected] go through it.
+    pub fn protected(self, scope: Option<&str>) -> Result<&'a WartIDSession, Rejection> {
+        let session = self.rocket().map_err(Rejection::Login)?;
+
+        match scope {
+            Some(scope) if !session.has_scope(scope) => {
+                log::debug!(
+                    "[WartIDSessionOrRedirect::protected] scope {} not granted",
+                    scope
+                );
+                Err(Rejection::Forbidden)
+            }
+            _ => Ok(session),
+        }
+    }
+}
+
+/// Why a [protected][WartIDSessionOrRedirect::protected] route was refused
+#[derive(Debug)]
+pub enum Rejection {
+    /// The user isn't logged in, and is redirected to the login page
+    Login(Redirect),
+    /// The session wasn't granted the scope the route requires, answered with `403 Forbidden`
+    Forbidden,
+}
+
+impl<'r> Responder<'r, 'static> for Rejection {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        match self {
+            Self::Login(redirect) => redirect.respond_to(request),
+            Self::Forbidden => Err(Status::Forbidden),
+        }
     }
 }
 
@@ -305,11 +1555,28 @@ impl<'r> FromRequest<'r> for WartIDSessionOrRedirect<'r> {
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let session: Outcome<&WartIDSession, WartIDSessionError> = request.guard().await;
+        if let Outcome::Failure(failure @ (_, WartIDSessionError::MissingContext)) = session {
+            return Outcome::Failure(failure);
+        }
 
+        let login = match resolve_context(request) {
+            Some(context) => &context.urls.login,
+            None => {
+                return Outcome::Failure((
+                    Status::InternalServerError,
+                    WartIDSessionError::MissingContext,
+                ))
+            }
+        };
+
+        // The session guard only forwards when no one is logged in, which is then handled the same
+        // as a logged out failure: by redirecting
         match session {
-            Outcome::Success(s) => Outcome::Success(Self(Some(s))),
-            Outcome::Forward(()) => Outcome::Forward(()),
-            Outcome::Failure((_, err)) if err.is_logged_out() => Outcome::Success(Self(None)),
+            Outcome::Success(s) => Outcome::Success(Self(Some(s), login)),
+            Outcome::Forward(()) => Outcome::Success(Self(None, login)),
+            Outcome::Failure((_, err)) if err.is_logged_out() => {
+                Outcome::Success(Self(None, login))
+            }
             Outcome::Failure(f) => Outcome::Failure(f),
         }
     }
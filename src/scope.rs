@@ -0,0 +1,75 @@
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// Scopes [warn_not_granted] already warned about
+    static ref NOT_GRANTED: Mutex<HashSet<String>> = Mutex::default();
+}
+
+/// Warns about requested scopes WartID didn't grant, once per scope for the whole process
+///
+/// Besides users refusing them, they're usually missing from the client's registration, in which
+/// case every login lacks them: one warning is enough for operators to notice.
+pub(crate) fn warn_not_granted(scopes: &[&str]) {
+    let mut warned = NOT_GRANTED.lock().unwrap_or_else(|err| err.into_inner());
+
+    for scope in scopes {
+        if warned.insert(scope.to_string()) {
+            log::warn!(
+                "[warn_not_granted] scope {} requested but not granted, check it's allowed for the \
+                client",
+                scope,
+            );
+        }
+    }
+}
+
+/// OAuth2 scope requested from WartID
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// Identity of the user: id and name
+    Basic,
+    Email,
+    OpenId,
+    Profile,
+    /// Refresh tokens outliving the browser session
+    OfflineAccess,
+    /// Any other scope
+    Custom(&'static str),
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Basic => "basic",
+            Self::Email => "email",
+            Self::OpenId => "openid",
+            Self::Profile => "profile",
+            Self::OfflineAccess => "offline_access",
+            Self::Custom(scope) => scope,
+        }
+    }
+
+    /// Space-separated list of scopes, as sent to WartID
+    pub fn join<I: IntoIterator<Item = Scope>>(scopes: I) -> String {
+        scopes
+            .into_iter()
+            .map(Scope::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl serde::Serialize for Scope {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
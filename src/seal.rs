@@ -0,0 +1,101 @@
+//! # Session sealing
+//!
+//! [SessionSealer] encrypts the session and its tokens with a key of its own, so keeping them
+//! client-side doesn't depend on the cookie encryption of the web framework. A payload sealed by
+//! one framework integration can be opened by another one using the same key.
+
+use crate::store::StoredSession;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use std::fmt;
+
+const NONCE_LENGTH: usize = 24;
+
+/// Error returned by [SessionSealer::open]
+#[derive(Debug)]
+pub enum SealError {
+    /// The payload isn't valid base64 or is too short to be sealed
+    Malformed,
+    /// The payload was tampered with, or sealed with another key
+    Decryption,
+    /// The decrypted payload isn't a session
+    Decoding(serde_json::Error),
+}
+
+impl fmt::Display for SealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "malformed sealed session"),
+            Self::Decryption => write!(f, "sealed session decryption failed"),
+            Self::Decoding(err) => write!(f, "invalid sealed session: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SealError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decoding(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Seals sessions with XChaCha20-Poly1305
+///
+/// Sealed payloads are the URL-safe base64 of a random nonce followed by the encrypted JSON of
+/// the session.
+#[derive(Clone)]
+pub struct SessionSealer {
+    cipher: XChaCha20Poly1305,
+}
+
+impl SessionSealer {
+    /// The key must be kept secret, and the same across restarts and instances of the app for
+    /// sessions to survive them
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    pub fn seal(&self, session: &StoredSession) -> String {
+        let plaintext = serde_json::to_vec(session).unwrap();
+
+        let mut nonce = [0; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher
+                .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+                .expect("session encryption failed"),
+        );
+
+        base64::encode_config(sealed, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn open(&self, sealed: &str) -> Result<StoredSession, SealError> {
+        let sealed = base64::decode_config(sealed, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| SealError::Malformed)?;
+
+        if sealed.len() < NONCE_LENGTH {
+            return Err(SealError::Malformed);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| SealError::Decryption)?;
+
+        serde_json::from_slice(&plaintext).map_err(SealError::Decoding)
+    }
+}
+
+impl fmt::Debug for SessionSealer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionSealer").finish()
+    }
+}
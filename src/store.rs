@@ -0,0 +1,92 @@
+//! # Server-side session storage
+//!
+//! By default, the session and the user's tokens are kept in (private) cookies. Setting a
+//! [SessionStore] on the [WIDContext][crate::WIDContext] keeps them server-side instead, the only
+//! cookie left being an opaque session id.
+
+use crate::WartIDSession;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[cfg(feature = "redis")]
+mod redis;
+
+#[cfg(feature = "redis")]
+pub use self::redis::RedisSessionStore;
+
+pub type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+const SESSION_ID_LENGTH: usize = 32;
+
+/// Everything needed to restore a session: the user's identity and their tokens
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct StoredSession {
+    #[serde(with = "crate::versioning")]
+    pub session: WartIDSession,
+    pub access_token: String,
+    /// `None` if WartID didn't issue one, the session then ends with the access token
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+/// Storage for sessions, keyed by an opaque session id
+#[async_trait::async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn get(&self, id: &str) -> Result<Option<StoredSession>, StoreError>;
+
+    async fn set(&self, id: &str, session: &StoredSession) -> Result<(), StoreError>;
+
+    async fn remove(&self, id: &str) -> Result<(), StoreError>;
+}
+
+/// In-memory [SessionStore]
+///
+/// Sessions are lost on restart and aren't shared between instances of the app.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: RwLock<HashMap<String, String>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn get(&self, id: &str) -> Result<Option<StoredSession>, StoreError> {
+        let sessions = self.sessions.read().unwrap();
+
+        match sessions.get(id) {
+            Some(serialized) => Ok(Some(serde_json::from_str(serialized)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, id: &str, session: &StoredSession) -> Result<(), StoreError> {
+        let serialized = serde_json::to_string(session)?;
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(id.to_string(), serialized);
+
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), StoreError> {
+        self.sessions.write().unwrap().remove(id);
+
+        Ok(())
+    }
+}
+
+pub(crate) fn new_session_id() -> String {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SESSION_ID_LENGTH)
+        .map(char::from)
+        .collect()
+}
@@ -0,0 +1,83 @@
+//! Redis-backed [SessionStore]
+//!
+//! Sessions are kept under `<namespace><session id>` keys. When several apps share the same Redis
+//! instance, give each of them its own [namespace][RedisSessionStore::with_namespace] so their
+//! sessions don't collide.
+
+use super::{SessionStore, StoreError, StoredSession};
+use ::redis::AsyncCommands;
+use std::time::Duration;
+
+const DEFAULT_NAMESPACE: &str = "wartid:session:";
+
+/// Default TTL of the stored sessions, should match the lifetime of WartID's refresh tokens
+const DEFAULT_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// [SessionStore] sharing sessions between instances of the app through Redis
+pub struct RedisSessionStore {
+    client: ::redis::Client,
+    namespace: String,
+    ttl: Duration,
+}
+
+impl RedisSessionStore {
+    pub fn new(client: ::redis::Client) -> Self {
+        Self {
+            client,
+            namespace: DEFAULT_NAMESPACE.into(),
+            ttl: DEFAULT_TTL,
+        }
+    }
+
+    /// Connects to the Redis instance at the given URL, like `redis://127.0.0.1:6379/0`
+    pub fn open(url: &str) -> Result<Self, ::redis::RedisError> {
+        Ok(Self::new(::redis::Client::open(url)?))
+    }
+
+    /// Replaces the default `wartid:session:` prefix of the keys
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// Sets how long sessions are kept after their last write, defaults to 30 days
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}{}", self.namespace, id)
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn get(&self, id: &str) -> Result<Option<StoredSession>, StoreError> {
+        let mut connection = self.client.get_async_connection().await?;
+        let serialized: Option<String> = connection.get(self.key(id)).await?;
+
+        match serialized {
+            Some(serialized) => Ok(Some(serde_json::from_str(&serialized)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, id: &str, session: &StoredSession) -> Result<(), StoreError> {
+        let serialized = serde_json::to_string(session)?;
+
+        let mut connection = self.client.get_async_connection().await?;
+        connection
+            .set_ex::<_, _, ()>(self.key(id), serialized, self.ttl.as_secs() as usize)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), StoreError> {
+        let mut connection = self.client.get_async_connection().await?;
+        connection.del::<_, ()>(self.key(id)).await?;
+
+        Ok(())
+    }
+}
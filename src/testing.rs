@@ -0,0 +1,644 @@
+//! # Mock WartID instance for tests
+//!
+//! [MockIdp] is a tiny in-process OAuth2 provider implementing the authorize, token, userinfo,
+//! JWKS and introspection endpoints with predictable responses, so apps can test their whole login
+//! flow without network access.
+//!
+//! ```no_run
+//! # async fn test() {
+//! use wartid_client::testing::MockIdp;
+//! use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls};
+//!
+//! let idp = MockIdp::start().await;
+//! let context = WIDContext::new(
+//!     WIDContextUrls::from_base_url("http://localhost:8000"),
+//!     WIDContextCredentials::new("client".into(), "secret".into()),
+//! )
+//! .with_client(idp.client());
+//!
+//! // Mount the routes, then log in with `idp.log_in(&client).await`, or request
+//! // `/oauth2/wartid/login` and pass its `Location` header to `idp.authorize` to obtain the
+//! // callback URL to request next
+//! # }
+//! ```
+
+use crate::{Client, WIDContextEndpoints};
+use chrono::Utc;
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, HOST, LOCATION, RETRY_AFTER};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use jsonwebtoken::{Algorithm, EncodingKey};
+use reqwest::Url;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Keys signing the tokens, published by the JWKS endpoint. Only meant for tests!
+const MOCK_RSA_KEY: &str = include_str!("testing/mock_rsa.pem");
+const MOCK_EC_KEY: &str = include_str!("testing/mock_ec.pem");
+const MOCK_ED25519_KEY: &str = include_str!("testing/mock_ed25519.pem");
+const MOCK_JWKS: &str = include_str!("testing/mock_jwks.json");
+
+struct MockState {
+    userinfo: serde_json::Value,
+    token_lifetime: i64,
+    token_type: String,
+    rotate_refresh_tokens: bool,
+    issue_refresh_tokens: bool,
+    opaque_tokens: bool,
+    id_token_issued_at: i64,
+    id_token_not_before: i64,
+    id_token_algorithm: Algorithm,
+    id_token_issuer: String,
+    /// Whether the `at_hash` of the ID tokens is computed from another access token than theirs
+    at_hash_mismatch: bool,
+    /// Issued authorization codes and refresh tokens, with the scope they grant
+    codes: HashMap<String, String>,
+    refresh_tokens: HashMap<String, String>,
+    /// Claims of the issued access tokens, answered by the introspection endpoint
+    access_tokens: HashMap<String, serde_json::Value>,
+    issued: u64,
+    refreshes: u64,
+    jwks_fetches: u64,
+    /// Number of next requests answered with `429 Too Many Requests`, and their `Retry-After`
+    rate_limited: (u32, Option<u64>),
+    /// Whether requests are answered with `503 Service Unavailable`
+    unavailable: bool,
+}
+
+impl MockState {
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.issued += 1;
+        format!("{}-{}", prefix, self.issued)
+    }
+
+    fn access_token(&mut self, client_id: &str, scope: &str) -> String {
+        let mut claims = serde_json::json!({
+            "sub": self.userinfo["sub"],
+            "aud": client_id,
+            "scope": scope,
+            "exp": Utc::now().timestamp() + self.token_lifetime,
+        });
+
+        let token = if self.opaque_tokens {
+            self.next_id("opaque")
+        } else {
+            claims["jti"] = self.next_id("access").into();
+
+            let mut header = jsonwebtoken::Header::new(Algorithm::RS256);
+            header.kid = Some("mock".into());
+            let key = EncodingKey::from_rsa_pem(MOCK_RSA_KEY.as_bytes()).unwrap();
+
+            jsonwebtoken::encode(&header, &claims, &key).unwrap()
+        };
+
+        self.access_tokens.insert(token.clone(), claims);
+        token
+    }
+
+    fn id_token(&self, client_id: &str, access_token: &str) -> String {
+        #[derive(serde::Serialize)]
+        struct Claims<'a> {
+            iss: &'a str,
+            aud: &'a str,
+            exp: i64,
+            iat: i64,
+            nbf: i64,
+            at_hash: String,
+            #[serde(flatten)]
+            userinfo: &'a serde_json::Value,
+        }
+
+        let hashed = if self.at_hash_mismatch {
+            "another-access-token"
+        } else {
+            access_token
+        };
+        // Ed25519 signs with SHA-512, the other keys of the mock with SHA-256
+        let digest = match self.id_token_algorithm {
+            Algorithm::EdDSA => Sha512::digest(hashed.as_bytes()).to_vec(),
+            _ => Sha256::digest(hashed.as_bytes()).to_vec(),
+        };
+
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            iss: &self.id_token_issuer,
+            aud: client_id,
+            exp: now + 3600,
+            iat: now + self.id_token_issued_at,
+            nbf: now + self.id_token_not_before,
+            at_hash: base64::encode_config(&digest[..digest.len() / 2], base64::URL_SAFE_NO_PAD),
+            userinfo: &self.userinfo,
+        };
+
+        let (kid, key) = match self.id_token_algorithm {
+            Algorithm::ES256 => (
+                "mock-es256",
+                EncodingKey::from_ec_pem(MOCK_EC_KEY.as_bytes()),
+            ),
+            Algorithm::EdDSA => (
+                "mock-eddsa",
+                EncodingKey::from_ed_pem(MOCK_ED25519_KEY.as_bytes()),
+            ),
+            _ => ("mock", EncodingKey::from_rsa_pem(MOCK_RSA_KEY.as_bytes())),
+        };
+
+        let mut header = jsonwebtoken::Header::new(self.id_token_algorithm);
+        header.kid = Some(kid.into());
+
+        jsonwebtoken::encode(&header, &claims, &key.unwrap()).unwrap()
+    }
+
+    fn token_response(
+        &mut self,
+        client_id: &str,
+        scope: &str,
+        refresh_token: Option<String>,
+    ) -> serde_json::Value {
+        let refresh_token = match refresh_token {
+            _ if !self.issue_refresh_tokens => None,
+            Some(refresh_token) if !self.rotate_refresh_tokens => Some(refresh_token),
+            _ => {
+                let refresh_token = self.next_id("refresh");
+                self.refresh_tokens
+                    .insert(refresh_token.clone(), scope.to_string());
+                Some(refresh_token)
+            }
+        };
+
+        let access_token = self.access_token(client_id, scope);
+        let mut response = serde_json::json!({
+            "id_token": self.id_token(client_id, &access_token),
+            "access_token": access_token,
+            "token_type": self.token_type,
+        });
+        if let Some(refresh_token) = refresh_token {
+            response["refresh_token"] = refresh_token.into();
+        }
+
+        // expires_in can't be negative, the client reads the expiry from the token in that case
+        if self.token_lifetime > 0 {
+            response["expires_in"] = self.token_lifetime.into();
+        }
+
+        response
+    }
+}
+
+/// In-process mock of a WartID instance, shut down when dropped
+pub struct MockIdp {
+    address: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockIdp {
+    /// Starts the mock on a random local port
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockState {
+            userinfo: serde_json::json!({
+                "sub": "00000000-0000-0000-0000-000000000001",
+                "name": "Mock User",
+                "email": "mock@example.com",
+            }),
+            token_lifetime: 3600,
+            token_type: "Bearer".into(),
+            rotate_refresh_tokens: true,
+            issue_refresh_tokens: true,
+            opaque_tokens: false,
+            id_token_issued_at: 0,
+            id_token_not_before: 0,
+            id_token_algorithm: Algorithm::RS256,
+            id_token_issuer: String::new(),
+            at_hash_mismatch: false,
+            codes: HashMap::new(),
+            refresh_tokens: HashMap::new(),
+            access_tokens: HashMap::new(),
+            issued: 0,
+            refreshes: 0,
+            jwks_fetches: 0,
+            rate_limited: (0, None),
+            unavailable: false,
+        }));
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("can't bind the mock IdP");
+        let address = listener.local_addr().unwrap();
+        state.lock().unwrap().id_token_issuer = format!("http://{}", address);
+
+        let service_state = Arc::clone(&state);
+        let make_service = make_service_fn(move |_| {
+            let state = Arc::clone(&service_state);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    handle(request, Arc::clone(&state))
+                }))
+            }
+        });
+
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                shutdown_rx.await.ok();
+            });
+
+        tokio::spawn(server);
+
+        Self {
+            address,
+            state,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// Base URL of the mock, without a trailing slash
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    pub fn endpoints(&self) -> WIDContextEndpoints {
+        WIDContextEndpoints::from_base_url(&self.base_url())
+    }
+
+    /// [Client] targeting the mock, including its issuer and JWKS endpoint
+    pub fn client(&self) -> Client {
+        Client::builder()
+            .endpoints(self.endpoints())
+            .issuer(self.base_url())
+            .jwks(format!("{}/oauth2/jwks", self.base_url()))
+            .build()
+            .unwrap()
+    }
+
+    /// Replaces the userinfo returned to the next requests. Its `sub` is also used as the subject
+    /// of the next access tokens.
+    pub fn set_userinfo(&self, userinfo: serde_json::Value) {
+        self.state.lock().unwrap().userinfo = userinfo;
+    }
+
+    /// Sets the lifetime of the next access tokens, in seconds. A negative value issues already
+    /// expired tokens, to simulate expiry.
+    pub fn set_token_lifetime(&self, seconds: i64) {
+        self.state.lock().unwrap().token_lifetime = seconds;
+    }
+
+    /// Sets the `token_type` of the next token responses, `Bearer` by default
+    pub fn set_token_type(&self, token_type: &str) {
+        self.state.lock().unwrap().token_type = token_type.to_string();
+    }
+
+    /// Sets whether refreshing tokens also issues a new refresh token, invalidating the old one
+    /// (the default)
+    pub fn set_rotate_refresh_tokens(&self, rotate: bool) {
+        self.state.lock().unwrap().rotate_refresh_tokens = rotate;
+    }
+
+    /// Sets whether the next token responses hold a refresh token (the default), as providers only
+    /// do when `offline_access` is granted
+    pub fn set_issue_refresh_tokens(&self, issue: bool) {
+        self.state.lock().unwrap().issue_refresh_tokens = issue;
+    }
+
+    /// Sets whether the next access tokens are opaque strings rather than JWTs. Their expiry is
+    /// then only given by `expires_in`.
+    pub fn set_opaque_tokens(&self, opaque: bool) {
+        self.state.lock().unwrap().opaque_tokens = opaque;
+    }
+
+    /// Issues an access token for the given client and scopes, as if the user had logged in to
+    /// it, e.g. to authenticate API requests with as a bearer token
+    pub fn issue_access_token(&self, client_id: &str, scope: &str) -> String {
+        self.state.lock().unwrap().access_token(client_id, scope)
+    }
+
+    /// Sets the `iat` and `nbf` claims of the next ID tokens, in seconds relative to the time
+    /// they're issued, to simulate clock skew
+    pub fn set_id_token_times(&self, issued_at: i64, not_before: i64) {
+        let mut state = self.state.lock().unwrap();
+        state.id_token_issued_at = issued_at;
+        state.id_token_not_before = not_before;
+    }
+
+    /// Sets the `iss` claim of the next ID tokens, the mock's base URL by default, e.g. to
+    /// simulate tokens of another provider
+    pub fn set_id_token_issuer(&self, issuer: &str) {
+        self.state.lock().unwrap().id_token_issuer = issuer.to_string();
+    }
+
+    /// Sets the algorithm signing the next ID tokens, among `RS256` (the default), `ES256` and
+    /// `EdDSA`
+    pub fn set_id_token_algorithm(&self, algorithm: Algorithm) {
+        assert!(
+            matches!(
+                algorithm,
+                Algorithm::RS256 | Algorithm::ES256 | Algorithm::EdDSA
+            ),
+            "the mock has no {:?} key",
+            algorithm
+        );
+        self.state.lock().unwrap().id_token_algorithm = algorithm;
+    }
+
+    /// Sets whether the `at_hash` claim of the next ID tokens doesn't match their access token, as
+    /// if one of them had been swapped
+    pub fn set_at_hash_mismatch(&self, mismatch: bool) {
+        self.state.lock().unwrap().at_hash_mismatch = mismatch;
+    }
+
+    /// Invalidates the refresh tokens issued so far, as if they had expired or been revoked
+    pub fn revoke_refresh_tokens(&self) {
+        self.state.lock().unwrap().refresh_tokens.clear();
+    }
+
+    /// Number of successful refresh token grants
+    pub fn refresh_count(&self) -> u64 {
+        self.state.lock().unwrap().refreshes
+    }
+
+    /// Answers the next `count` requests with `429 Too Many Requests`, with a `Retry-After` of
+    /// `retry_after` seconds if some
+    pub fn rate_limit(&self, count: u32, retry_after: Option<u64>) {
+        self.state.lock().unwrap().rate_limited = (count, retry_after);
+    }
+
+    /// Sets whether requests are answered with `503 Service Unavailable`, to simulate an outage
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.state.lock().unwrap().unavailable = unavailable;
+    }
+
+    /// Number of requests to the JWKS endpoint
+    pub fn jwks_fetch_count(&self) -> u64 {
+        self.state.lock().unwrap().jwks_fetches
+    }
+
+    /// Plays the user approving the login: takes the authorization URL the app redirected to, and
+    /// returns the callback URL WartID would redirect back to
+    pub fn authorize(&self, authorize_url: &str) -> String {
+        let url = Url::parse(authorize_url).expect("invalid authorization URL");
+        let query = url.query().unwrap_or_default();
+
+        authorize(query, &self.state).expect("invalid authorization request")
+    }
+
+    /// Drives a whole login on a Rocket client: requests `/oauth2/wartid/login`, approves it, then
+    /// requests the callback and returns its response. A tracked client keeps the session cookies.
+    #[cfg(feature = "rocket")]
+    pub async fn log_in<'c>(
+        &self,
+        client: &'c rocket::local::asynchronous::Client,
+    ) -> rocket::local::asynchronous::LocalResponse<'c> {
+        self.log_in_from(client, "/oauth2/wartid/login").await
+    }
+
+    /// Same as [log_in][MockIdp::log_in], starting the flow with the given login URI, e.g. one
+    /// with a `next` parameter
+    #[cfg(feature = "rocket")]
+    pub async fn log_in_from<'c>(
+        &self,
+        client: &'c rocket::local::asynchronous::Client,
+        login_uri: &str,
+    ) -> rocket::local::asynchronous::LocalResponse<'c> {
+        let login = client.get(login_uri.to_string()).dispatch().await;
+        let authorize_url = login
+            .headers()
+            .get_one("Location")
+            .expect("the login didn't redirect to WartID");
+        let callback = Url::parse(&self.authorize(authorize_url)).expect("invalid callback URL");
+
+        let callback_uri = match callback.query() {
+            Some(query) => format!("{}?{}", callback.path(), query),
+            None => callback.path().to_string(),
+        };
+        client.get(callback_uri).dispatch().await
+    }
+}
+
+impl Drop for MockIdp {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
+
+fn authorize(query: &str, state: &Mutex<MockState>) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct Authorize {
+        redirect_uri: String,
+        state: String,
+        #[serde(default)]
+        scope: String,
+    }
+
+    let authorize: Authorize = serde_urlencoded::from_str(query).ok()?;
+
+    let mut state = state.lock().unwrap();
+    let code = state.next_id("code");
+    state.codes.insert(code.clone(), authorize.scope);
+
+    let callback =
+        serde_urlencoded::to_string(&[("code", &code), ("state", &authorize.state)]).unwrap();
+
+    Some(format!("{}?{}", authorize.redirect_uri, callback))
+}
+
+fn json(status: StatusCode, body: &serde_json::Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn oauth_error(error: &str) -> Response<Body> {
+    json(
+        StatusCode::BAD_REQUEST,
+        &serde_json::json!({ "error": error }),
+    )
+}
+
+async fn handle(
+    request: Request<Body>,
+    state: Arc<Mutex<MockState>>,
+) -> Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    {
+        let mut state = state.lock().unwrap();
+        if state.rate_limited.0 > 0 {
+            state.rate_limited.0 -= 1;
+
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+            if let Some(retry_after) = state.rate_limited.1 {
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+            }
+            return Ok(response);
+        }
+
+        if state.unavailable {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            return Ok(response);
+        }
+    }
+
+    let response = match (method, path.as_str()) {
+        (Method::GET, "/oauth2/authorize") => {
+            match authorize(request.uri().query().unwrap_or_default(), &state) {
+                Some(location) => {
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = StatusCode::FOUND;
+                    response
+                        .headers_mut()
+                        .insert(LOCATION, HeaderValue::from_str(&location).unwrap());
+                    response
+                }
+                None => oauth_error("invalid_request"),
+            }
+        }
+        (Method::POST, "/oauth2/token") => {
+            #[derive(serde::Deserialize)]
+            struct TokenForm {
+                grant_type: String,
+                code: Option<String>,
+                refresh_token: Option<String>,
+                #[serde(default)]
+                client_id: String,
+                scope: Option<String>,
+            }
+
+            let body = hyper::body::to_bytes(request.into_body())
+                .await
+                .unwrap_or_default();
+
+            match serde_urlencoded::from_bytes::<TokenForm>(&body) {
+                Ok(form) => {
+                    let mut state = state.lock().unwrap();
+
+                    match (form.grant_type.as_str(), form.code, form.refresh_token) {
+                        ("authorization_code", Some(code), _)
+                            if state.codes.contains_key(&code) =>
+                        {
+                            let scope = state.codes.remove(&code).unwrap_or_default();
+                            json(
+                                StatusCode::OK,
+                                &state.token_response(&form.client_id, &scope, None),
+                            )
+                        }
+                        ("refresh_token", _, Some(refresh_token))
+                            if state.refresh_tokens.contains_key(&refresh_token) =>
+                        {
+                            let scope = match &form.scope {
+                                Some(scope) => scope.clone(),
+                                None => state.refresh_tokens[&refresh_token].clone(),
+                            };
+                            if state.rotate_refresh_tokens {
+                                state.refresh_tokens.remove(&refresh_token);
+                            }
+                            state.refreshes += 1;
+                            let mut response =
+                                state.token_response(&form.client_id, &scope, Some(refresh_token));
+                            // Narrowed scopes are granted as is
+                            if let Some(scope) = form.scope {
+                                response["scope"] = scope.into();
+                            }
+                            json(StatusCode::OK, &response)
+                        }
+                        _ => oauth_error("invalid_grant"),
+                    }
+                }
+                Err(_) => oauth_error("invalid_request"),
+            }
+        }
+        (Method::POST, "/oauth2/introspect") => {
+            #[derive(serde::Deserialize)]
+            struct IntrospectionForm {
+                token: String,
+            }
+
+            let body = hyper::body::to_bytes(request.into_body())
+                .await
+                .unwrap_or_default();
+
+            match serde_urlencoded::from_bytes::<IntrospectionForm>(&body) {
+                Ok(form) => {
+                    let state = state.lock().unwrap();
+                    let now = Utc::now().timestamp();
+
+                    match state.access_tokens.get(&form.token) {
+                        Some(claims) if claims["exp"].as_i64().unwrap_or_default() >= now => {
+                            let mut response = claims.clone();
+                            response["active"] = true.into();
+                            json(StatusCode::OK, &response)
+                        }
+                        _ => json(StatusCode::OK, &serde_json::json!({ "active": false })),
+                    }
+                }
+                Err(_) => oauth_error("invalid_request"),
+            }
+        }
+        (Method::GET, "/.well-known/openid-configuration") => {
+            let host = request
+                .headers()
+                .get(HOST)
+                .and_then(|host| host.to_str().ok())
+                .unwrap_or_default();
+            let base = format!("http://{}", host);
+
+            json(
+                StatusCode::OK,
+                &serde_json::json!({
+                    "issuer": base,
+                    "authorization_endpoint": format!("{}/oauth2/authorize", base),
+                    "token_endpoint": format!("{}/oauth2/token", base),
+                    "userinfo_endpoint": format!("{}/oauth2/userinfo", base),
+                    "jwks_uri": format!("{}/oauth2/jwks", base),
+                    "introspection_endpoint": format!("{}/oauth2/introspect", base),
+                }),
+            )
+        }
+        (Method::GET, "/oauth2/jwks") => {
+            state.lock().unwrap().jwks_fetches += 1;
+            json(StatusCode::OK, &serde_json::from_str(MOCK_JWKS).unwrap())
+        }
+        (Method::GET, "/oauth2/userinfo") => {
+            let authorized = request
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|header| header.to_str().ok())
+                .map_or(false, |header| header.starts_with("Bearer "));
+
+            if authorized {
+                json(StatusCode::OK, &state.lock().unwrap().userinfo)
+            } else {
+                let mut response = json(
+                    StatusCode::UNAUTHORIZED,
+                    &serde_json::json!({ "error": "invalid_token" }),
+                );
+                response.headers_mut().insert(
+                    hyper::header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static("Bearer error=\"invalid_token\""),
+                );
+                response
+            }
+        }
+        _ => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    };
+
+    Ok(response)
+}
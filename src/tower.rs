@@ -0,0 +1,208 @@
+//! # Tower support
+//!
+//! [WartIDLayer] authenticates requests for any [tower] service (hyper, axum, tonic...), and
+//! inserts the [WartIDSession] into their extensions.
+//!
+//! Requests are authenticated with:
+//!   * the session cookie set by another integration, provided the context has a
+//!     [session sealer][crate::WIDContext::with_session_sealer] to open it
+//!   * an `Authorization: Bearer` access token issued for the app, checked against WartID, if the
+//!     context [accepts them][crate::WIDContext::with_bearer_tokens], before or after the cookie
+//!     as it tells
+//!
+//! Cookie sessions aren't refreshed, as the layer can't write cookies back: they're rejected once
+//! their access token expired, until the app refreshes them.
+//!
+//! ```no_run
+//! # fn service<S>(service: S, context: wartid_client::WIDContext) {
+//! use std::sync::Arc;
+//! use tower_layer::Layer;
+//! use wartid_client::tower::WartIDLayer;
+//!
+//! let service = WartIDLayer::required(Arc::new(context)).layer(service);
+//! # }
+//! ```
+//!
+//! [tower]: https://docs.rs/tower
+
+use crate::api::Authorization;
+use crate::bearer::{authenticate_bearer, bearer_token};
+use crate::seal::SealError;
+use crate::{BearerTokens, WIDContext, WartIDSession, WartIDSessionError};
+use chrono::Utc;
+use http::header::{AUTHORIZATION, COOKIE};
+use http::{HeaderMap, Request, Response, StatusCode};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Layer authenticating requests, see the [module documentation][self]
+#[derive(Clone, Debug)]
+pub struct WartIDLayer {
+    context: Arc<WIDContext>,
+    required: bool,
+}
+
+impl WartIDLayer {
+    /// Rejects requests without a valid session with `401 Unauthorized`
+    pub fn required(context: Arc<WIDContext>) -> Self {
+        Self {
+            context,
+            required: true,
+        }
+    }
+
+    /// Passes every request through, with the session in their extensions if they have a valid
+    /// one, letting the inner service decide
+    pub fn optional(context: Arc<WIDContext>) -> Self {
+        Self {
+            context,
+            required: false,
+        }
+    }
+}
+
+impl<S> Layer<S> for WartIDLayer {
+    type Service = WartIDService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WartIDService {
+            inner,
+            context: self.context.clone(),
+            required: self.required,
+        }
+    }
+}
+
+/// Service built by [WartIDLayer]
+#[derive(Clone, Debug)]
+pub struct WartIDService<S> {
+    inner: S,
+    context: Arc<WIDContext>,
+    required: bool,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for WartIDService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+        // The clone may not be ready, so the instance polled by `poll_ready` is the one called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let context = self.context.clone();
+        let required = self.required;
+
+        Box::pin(async move {
+            match authenticate(&context, request.headers()).await {
+                Ok(session) => {
+                    request.extensions_mut().insert(session);
+                }
+                Err(err) if required => {
+                    log::debug!("[WartIDService::call] {:?}", err);
+
+                    let mut response = Response::new(ResBody::default());
+                    *response.status_mut() = match err {
+                        WartIDSessionError::Network => StatusCode::SERVICE_UNAVAILABLE,
+                        _ => StatusCode::UNAUTHORIZED,
+                    };
+                    return Ok(response);
+                }
+                Err(_) => {}
+            }
+
+            inner.call(request).await
+        })
+    }
+}
+
+/// Reads and validates the session of a request from its headers, like the Rocket session guards
+async fn authenticate(
+    context: &WIDContext,
+    headers: &HeaderMap,
+) -> Result<WartIDSession, WartIDSessionError> {
+    let bearer = match context.bearer_tokens {
+        BearerTokens::Ignored => None,
+        _ => bearer_token(
+            headers
+                .get(AUTHORIZATION)
+                .and_then(|header| header.to_str().ok()),
+        ),
+    };
+
+    if let (Some(token), BearerTokens::Preferred) = (bearer, context.bearer_tokens) {
+        return authenticate_bearer(context, token).await;
+    }
+
+    match (cookie_session(context, headers), bearer) {
+        (Err(WartIDSessionError::MissingAuthorization), Some(token)) => {
+            authenticate_bearer(context, token).await
+        }
+        (result, _) => result,
+    }
+}
+
+/// Reads and validates the sealed session cookie
+fn cookie_session(
+    context: &WIDContext,
+    headers: &HeaderMap,
+) -> Result<WartIDSession, WartIDSessionError> {
+    let sealer = match &context.sealer {
+        Some(sealer) => sealer,
+        None => return Err(WartIDSessionError::MissingAuthorization),
+    };
+
+    let sealed = match cookie(headers, &context.cookie_name("sealed")) {
+        Some(sealed) => sealed,
+        None => return Err(WartIDSessionError::MissingAuthorization),
+    };
+
+    let stored = match sealer.open(sealed) {
+        Ok(stored) => stored,
+        Err(err @ SealError::Decoding(_)) => {
+            log::warn!("[authenticate] {}", err);
+            return Err(WartIDSessionError::Outdated);
+        }
+        Err(err) => {
+            log::error!("[authenticate] {}", err);
+            return Err(WartIDSessionError::SessionDecoding);
+        }
+    };
+
+    if context.session_timed_out(&stored.session, Utc::now().timestamp()) {
+        return Err(WartIDSessionError::Expired);
+    }
+
+    let authorization = Authorization::new(&stored.access_token, stored.refresh_token.as_deref())
+        .with_expiry(stored.session.token_expires_at);
+    if authorization.expired(&context.client) {
+        return Err(WartIDSessionError::Expired);
+    }
+
+    Ok(stored.session)
+}
+
+fn cookie<'h>(headers: &'h HeaderMap, name: &str) -> Option<&'h str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value)
+}
@@ -0,0 +1,80 @@
+//! Versioning of the serialized session
+//!
+//! Sessions are serialized with a `v` field holding [SESSION_VERSION]. Sessions saved by older
+//! versions of the crate go through the [MIGRATIONS] they missed before being deserialized, and
+//! those that still can't be are reported as [Outdated][crate::WartIDSessionError::Outdated], so
+//! users log in again rather than being stuck with an error.
+
+use crate::WartIDSession;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+/// Migrations of the serialized session, the one at index `i` upgrading version `i` to `i + 1`
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[
+    // Sessions saved before versioning, all the fields added since have defaults
+    |_| {},
+];
+
+/// Version of the sessions serialized by this version of the crate
+pub(crate) const SESSION_VERSION: u64 = MIGRATIONS.len() as u64;
+
+#[derive(Serialize)]
+struct Versioned<'a> {
+    v: u64,
+    #[serde(flatten)]
+    session: &'a WartIDSession,
+}
+
+pub(crate) fn serialize<S: Serializer>(
+    session: &WartIDSession,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    Versioned {
+        v: SESSION_VERSION,
+        session,
+    }
+    .serialize(serializer)
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<WartIDSession, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    upgrade(value).map_err(D::Error::custom)
+}
+
+pub(crate) fn to_string(session: &WartIDSession) -> String {
+    serde_json::to_string(&Versioned {
+        v: SESSION_VERSION,
+        session,
+    })
+    .expect("sessions are always serializable")
+}
+
+pub(crate) fn from_str(serialized: &str) -> serde_json::Result<WartIDSession> {
+    upgrade(serde_json::from_str(serialized)?)
+}
+
+/// Applies the missing migrations to a serialized session, and deserializes it
+fn upgrade(value: Value) -> serde_json::Result<WartIDSession> {
+    let mut fields = match value {
+        Value::Object(fields) => fields,
+        _ => return Err(serde_json::Error::custom("session isn't an object")),
+    };
+
+    let version = match fields.remove("v") {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| serde_json::Error::custom("invalid session version"))?,
+        None => 0,
+    };
+
+    // Sessions of a newer version, e.g. during a rollback, are read as is since unknown fields
+    // are ignored
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        migration(&mut fields);
+    }
+
+    serde_json::from_value(Value::Object(fields))
+}
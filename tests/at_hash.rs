@@ -0,0 +1,62 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{
+    IdentitySource, WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession,
+};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+async fn callback_status(idp: &MockIdp, identity_source: IdentitySource) -> Status {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_identity_source(identity_source);
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    idp.log_in(&client).await.status()
+}
+
+#[rocket::async_test]
+async fn matching_at_hash_is_accepted() {
+    let idp = MockIdp::start().await;
+
+    let status = callback_status(&idp, IdentitySource::IdToken).await;
+    assert_eq!(status, Status::TemporaryRedirect);
+}
+
+#[rocket::async_test]
+async fn mismatched_at_hash_is_rejected() {
+    let idp = MockIdp::start().await;
+    idp.set_at_hash_mismatch(true);
+
+    let status = callback_status(&idp, IdentitySource::IdToken).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn at_hash_of_unverified_id_tokens_is_ignored() {
+    let idp = MockIdp::start().await;
+    idp.set_at_hash_mismatch(true);
+
+    // The userinfo endpoint vouches for the access token, the ID token isn't verified
+    let status = callback_status(&idp, IdentitySource::Userinfo).await;
+    assert_eq!(status, Status::TemporaryRedirect);
+}
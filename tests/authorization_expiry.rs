@@ -0,0 +1,82 @@
+use chrono::Utc;
+use std::time::Duration;
+use wartid_client::{Authorization, Client};
+
+/// Unsigned-ish JWT expiring `exp` seconds from now, its signature is never checked for expiry
+fn token_expiring_in(exp: i64) -> String {
+    #[derive(serde::Serialize)]
+    struct Claims {
+        sub: &'static str,
+        exp: i64,
+    }
+
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &Claims {
+            sub: "00000000-0000-0000-0000-000000000001",
+            exp: Utc::now().timestamp() + exp,
+        },
+        &jsonwebtoken::EncodingKey::from_secret(b"fixture"),
+    )
+    .unwrap()
+}
+
+#[test]
+fn future_exp_is_not_expired() {
+    let token = token_expiring_in(3600);
+
+    assert!(!Authorization::new(&token, None).expired(&Client::default()));
+}
+
+#[test]
+fn past_exp_is_expired() {
+    let token = token_expiring_in(-60);
+
+    assert!(Authorization::new(&token, None).expired(&Client::default()));
+}
+
+#[test]
+fn undecodable_token_is_expired() {
+    assert!(Authorization::new("not-a-jwt", None).expired(&Client::default()));
+}
+
+#[test]
+fn exp_within_the_leeway_is_expired() {
+    let token = token_expiring_in(30);
+    let client = Client::default().with_leeway(Duration::from_secs(60));
+
+    assert!(Authorization::new(&token, None).expired(&client));
+}
+
+#[test]
+fn exp_beyond_the_leeway_is_not_expired() {
+    let token = token_expiring_in(120);
+    let client = Client::default().with_leeway(Duration::from_secs(60));
+
+    assert!(!Authorization::new(&token, None).expired(&client));
+}
+
+#[test]
+fn stored_expiry_takes_precedence_over_the_token() {
+    let token = token_expiring_in(3600);
+    let authorization =
+        Authorization::new(&token, None).with_expiry(Some(Utc::now().timestamp() - 60));
+
+    assert!(authorization.expired(&Client::default()));
+}
+
+#[test]
+fn opaque_token_without_expiry_is_not_expired() {
+    let client = Client::default().with_opaque_tokens();
+
+    assert!(!Authorization::new("opaque", None).expired(&client));
+}
+
+#[test]
+fn opaque_token_with_past_expiry_is_expired() {
+    let client = Client::default().with_opaque_tokens();
+    let authorization =
+        Authorization::new("opaque", None).with_expiry(Some(Utc::now().timestamp() - 60));
+
+    assert!(authorization.expired(&client));
+}
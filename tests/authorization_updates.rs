@@ -0,0 +1,29 @@
+use wartid_client::Authorization;
+
+fn refreshed() -> Authorization<'static> {
+    Authorization::Dirty {
+        access_token: "access-2".into(),
+        refresh_token: "refresh-2".into(),
+        expires_at: Some(42),
+    }
+}
+
+#[test]
+fn refreshed_tokens_are_taken() {
+    assert_eq!(
+        refreshed().take_updated(),
+        Some(("access-2".into(), "refresh-2".into(), Some(42))),
+    );
+}
+
+#[test]
+fn clean_tokens_have_no_update() {
+    assert_eq!(Authorization::new("access", None).take_updated(), None);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "refreshed tokens dropped")]
+fn dropping_refreshed_tokens_panics() {
+    drop(refreshed());
+}
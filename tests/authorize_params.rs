@@ -0,0 +1,82 @@
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls};
+
+async fn authorize_url(login: Login) -> String {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    );
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/oauth2/wartid", wartid_client::rocket::routes(login));
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = client.get("/oauth2/wartid/login").dispatch().await;
+    response.headers().get_one("Location").unwrap().to_string()
+}
+
+#[rocket::async_test]
+async fn extra_params_are_appended_and_encoded() {
+    let url = authorize_url(
+        Login::basic()
+            .with_extra_param("kc_idp_hint", "github")
+            .with_extra_param("login_hint", "https://api.example.com/a b&c"),
+    )
+    .await;
+
+    let query = url.split_once('?').unwrap().1;
+    assert!(
+        query.ends_with("&kc_idp_hint=github&login_hint=https%3A%2F%2Fapi.example.com%2Fa+b%26c")
+    );
+}
+
+#[rocket::async_test]
+async fn extra_params_cannot_override_known_ones() {
+    let url = authorize_url(Login::basic().with_extra_param("client_id", "evil")).await;
+
+    assert!(url.contains("client_id=client"));
+    assert!(!url.contains("evil"));
+}
+
+#[rocket::async_test]
+async fn audience_is_requested() {
+    let url = authorize_url(Login::basic().with_audience("https://api.example.com")).await;
+    assert!(url.contains("&audience=https%3A%2F%2Fapi.example.com"));
+
+    let url = authorize_url(Login::basic()).await;
+    assert!(!url.contains("audience"));
+
+    // Extra params can't sneak in another audience
+    let url = authorize_url(
+        Login::basic()
+            .with_audience("https://api.example.com")
+            .with_extra_param("audience", "https://evil.example.com"),
+    )
+    .await;
+    assert!(!url.contains("evil"));
+}
+
+#[rocket::async_test]
+async fn account_selection_is_forced() {
+    let url = authorize_url(Login::basic().force_account_selection()).await;
+    assert!(url.contains("&prompt=select_account"));
+
+    let url = authorize_url(Login::basic()).await;
+    assert!(!url.contains("prompt"));
+}
+
+#[rocket::async_test]
+async fn prompt_values_are_combined() {
+    let url = authorize_url(
+        Login::basic()
+            .force_account_selection()
+            .with_prompt("consent")
+            .with_extra_param("prompt", "login select_account"),
+    )
+    .await;
+
+    assert!(url.contains("&prompt=select_account+consent+login"));
+    assert_eq!(url.matches("prompt=").count(), 1);
+}
@@ -0,0 +1,159 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::{Header, Status};
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{
+    BearerTokens, WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession,
+};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+#[get("/scopes")]
+fn scopes(session: &WartIDSession) -> String {
+    session.scopes.clone()
+}
+
+async fn client(idp: &MockIdp, bearer_tokens: BearerTokens) -> Client {
+    client_with(idp.client(), bearer_tokens).await
+}
+
+async fn client_with(wartid: wartid_client::Client, bearer_tokens: BearerTokens) -> Client {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(wartid)
+    .with_bearer_tokens(bearer_tokens);
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me, scopes])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+async fn get_me(client: &Client, bearer: Option<&str>) -> (Status, Option<String>) {
+    get(client, "/me", bearer).await
+}
+
+async fn get(client: &Client, uri: &str, bearer: Option<&str>) -> (Status, Option<String>) {
+    let mut request = client.get(uri.to_string());
+    if let Some(bearer) = bearer {
+        request = request.header(Header::new("Authorization", format!("Bearer {}", bearer)));
+    }
+
+    let response = request.dispatch().await;
+    (response.status(), response.into_string().await)
+}
+
+#[rocket::async_test]
+async fn bearer_token_authenticates_without_cookies() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, BearerTokens::Fallback).await;
+
+    let token = idp.issue_access_token("client", "basic");
+    let (status, name) = get_me(&client, Some(&token)).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(name.as_deref(), Some("Mock User"));
+}
+
+#[rocket::async_test]
+async fn missing_bearer_token_is_unauthorized() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, BearerTokens::Preferred).await;
+
+    let (status, _) = get_me(&client, None).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn bearer_tokens_are_ignored_by_default() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, BearerTokens::default()).await;
+
+    let token = idp.issue_access_token("client", "basic");
+    let (status, _) = get_me(&client, Some(&token)).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn tokens_of_other_clients_are_rejected() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, BearerTokens::Preferred).await;
+
+    let token = idp.issue_access_token("another-client", "basic");
+    let (status, _) = get_me(&client, Some(&token)).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn unsigned_tokens_are_rejected() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, BearerTokens::Preferred).await;
+
+    // Valid claims, signed with a key anyone can pick
+    let token = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &serde_json::json!({ "sub": "someone", "aud": "client", "exp": i64::MAX }),
+        &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+    )
+    .unwrap();
+    let (status, _) = get_me(&client, Some(&token)).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn session_has_the_scopes_of_the_token() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, BearerTokens::Preferred).await;
+
+    let token = idp.issue_access_token("client", "basic email");
+    let (status, scopes) = get(&client, "/scopes", Some(&token)).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(scopes.as_deref(), Some("basic email"));
+}
+
+#[rocket::async_test]
+async fn opaque_tokens_are_introspected() {
+    let idp = MockIdp::start().await;
+    idp.set_opaque_tokens(true);
+    let wartid = wartid_client::Client::builder()
+        .endpoints(idp.endpoints())
+        .introspection(format!("{}/oauth2/introspect", idp.base_url()))
+        .build()
+        .unwrap();
+    let client = client_with(wartid, BearerTokens::Preferred).await;
+
+    let token = idp.issue_access_token("client", "basic");
+    let (status, name) = get_me(&client, Some(&token)).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(name.as_deref(), Some("Mock User"));
+
+    let (status, _) = get_me(&client, Some("opaque-unknown")).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    let token = idp.issue_access_token("another-client", "basic");
+    let (status, _) = get_me(&client, Some(&token)).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn opaque_tokens_need_introspection() {
+    let idp = MockIdp::start().await;
+    idp.set_opaque_tokens(true);
+    let client = client(&idp, BearerTokens::Preferred).await;
+
+    let token = idp.issue_access_token("client", "basic");
+    let (status, _) = get_me(&client, Some(&token)).await;
+    assert_eq!(status, Status::Unauthorized);
+}
@@ -0,0 +1,111 @@
+use wartid_client::testing::MockIdp;
+use wartid_client::{Client, WIDContextEndpoints, WartIDError};
+
+#[rocket::async_test]
+async fn client_targets_the_given_endpoints() {
+    let idp = MockIdp::start().await;
+    let client = Client::new(idp.endpoints(), reqwest::Client::new());
+
+    let userinfo = client.request_userinfo_with_token("access").await.unwrap();
+    assert_eq!(userinfo.name(), "Mock User");
+}
+
+#[rocket::async_test]
+async fn userinfo_is_readable_by_direct_callers() {
+    let idp = MockIdp::start().await;
+    idp.set_userinfo(serde_json::json!({
+        "sub": "github|1234",
+        "name": "Mock User",
+        "preferred_username": "mock",
+        "email": "mock@example.com",
+        "email_verified": true,
+        "locale": "fr",
+    }));
+    let client = Client::new(idp.endpoints(), reqwest::Client::new());
+
+    let userinfo = client.request_userinfo_with_token("access").await.unwrap();
+    assert_eq!(userinfo.sub(), "github|1234");
+    assert_eq!(userinfo.preferred_username(), Some("mock"));
+    assert_eq!(userinfo.email(), Some("mock@example.com"));
+    assert!(userinfo.email_verified());
+    assert_eq!(userinfo.picture(), None);
+    assert_eq!(userinfo.extra()["locale"], "fr");
+}
+
+#[test]
+#[should_panic]
+fn invalid_endpoints_panic() {
+    let mut endpoints = WIDContextEndpoints::default();
+    endpoints.token = "not a URL".into();
+
+    Client::new(endpoints, reqwest::Client::new());
+}
+
+#[rocket::async_test]
+async fn discovery_goes_through_the_configured_proxy() {
+    let idp = MockIdp::start().await;
+
+    // Only reachable through the proxy, which the mock IdP plays, answering for the Host asked
+    let client = Client::builder()
+        .proxy(reqwest::Proxy::http(idp.base_url()).unwrap())
+        .discover("http://wartid.invalid")
+        .await
+        .unwrap()
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        client.jwks_url().map(|url| url.as_str()),
+        Some("http://wartid.invalid/oauth2/jwks"),
+    );
+}
+
+#[rocket::async_test]
+async fn discovery_sets_the_issuer() {
+    let idp = MockIdp::start().await;
+
+    let client = Client::builder()
+        .discover(&idp.base_url())
+        .await
+        .unwrap()
+        .build()
+        .unwrap();
+
+    assert_eq!(client.issuer(), Some(idp.base_url().as_str()));
+}
+
+#[rocket::async_test]
+async fn probe_reads_the_discovery_document_without_jwks() {
+    let idp = MockIdp::start().await;
+    let client = Client::new(idp.endpoints(), reqwest::Client::new());
+
+    client.probe().await.unwrap();
+    assert_eq!(idp.jwks_fetch_count(), 0);
+}
+
+#[rocket::async_test]
+async fn probe_reads_the_jwks() {
+    let idp = MockIdp::start().await;
+
+    idp.client().probe().await.unwrap();
+    assert_eq!(idp.jwks_fetch_count(), 1);
+}
+
+#[rocket::async_test]
+async fn probe_fails_on_error_responses() {
+    let idp = MockIdp::start().await;
+    idp.set_unavailable(true);
+
+    let result = idp.client().probe().await;
+    assert!(matches!(result, Err(WartIDError::Request(_))));
+}
+
+#[rocket::async_test]
+async fn probe_fails_when_unreachable() {
+    // Nothing listens on port 1
+    let endpoints = WIDContextEndpoints::from_base_url("http://127.0.0.1:1");
+    let client = Client::new(endpoints, reqwest::Client::new());
+
+    let result = client.probe().await;
+    assert!(matches!(result, Err(WartIDError::Request(_))));
+}
@@ -0,0 +1,122 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::Request;
+use wartid_client::handlers::Login;
+use wartid_client::rocket::ContextResolver;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+/// Tenants served under `/acme` and `/globex`, each with its own WartID instance
+struct Tenants {
+    acme: WIDContext,
+    globex: WIDContext,
+}
+
+impl ContextResolver for Tenants {
+    fn resolve<'r>(&'r self, request: &Request<'_>) -> Option<&'r WIDContext> {
+        let path = request.uri().path();
+
+        if path.starts_with("/acme/") {
+            Some(&self.acme)
+        } else if path.starts_with("/globex/") {
+            Some(&self.globex)
+        } else {
+            None
+        }
+    }
+}
+
+fn context(tenant: &str, idp: &MockIdp) -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url(&format!("http://localhost:8000/{}", tenant)),
+        WIDContextCredentials::new(format!("client-{}", tenant), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_tenant(tenant)
+}
+
+async fn client(acme: &MockIdp, globex: &MockIdp) -> Client {
+    let tenants: Box<dyn ContextResolver> = Box::new(Tenants {
+        acme: context("acme", acme),
+        globex: context("globex", globex),
+    });
+
+    let rocket = rocket::ignite()
+        .manage(tenants)
+        .mount("/acme", routes![me])
+        .mount("/globex", routes![me])
+        .mount(
+            "/acme/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        )
+        .mount(
+            "/globex/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn sets_cookie(response: &LocalResponse<'_>, name: &str) -> bool {
+    let prefix = format!("{}=", name);
+    response
+        .headers()
+        .get("Set-Cookie")
+        .any(|cookie| cookie.starts_with(&prefix))
+}
+
+#[rocket::async_test]
+async fn logins_use_the_context_of_their_tenant() {
+    let acme = MockIdp::start().await;
+    let globex = MockIdp::start().await;
+    let client = client(&acme, &globex).await;
+
+    let login = client.get("/acme/oauth2/wartid/login").dispatch().await;
+    let authorize_url = login.headers().get_one("Location").unwrap();
+    assert!(authorize_url.starts_with(&acme.base_url()));
+    assert!(authorize_url.contains("client_id=client-acme"));
+    assert!(sets_cookie(&login, "wartid_acme_auth_state"));
+
+    let response = acme.log_in_from(&client, "/acme/oauth2/wartid/login").await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert!(sets_cookie(&response, "wartid_acme_s"));
+    assert!(!sets_cookie(&response, "wartid_s"));
+    assert!(!sets_cookie(&response, "wartid_globex_s"));
+
+    let response = client.get("/acme/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+}
+
+#[rocket::async_test]
+async fn sessions_are_not_shared_between_tenants() {
+    let acme = MockIdp::start().await;
+    let globex = MockIdp::start().await;
+    globex.set_userinfo(serde_json::json!({
+        "sub": "00000000-0000-0000-0000-000000000002",
+        "name": "Globex User",
+    }));
+    let client = client(&acme, &globex).await;
+
+    acme.log_in_from(&client, "/acme/oauth2/wartid/login").await;
+
+    let response = client.get("/globex/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // Each tenant then keeps its own session
+    globex
+        .log_in_from(&client, "/globex/oauth2/wartid/login")
+        .await;
+
+    let response = client.get("/acme/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+    let response = client.get("/globex/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Globex User"));
+}
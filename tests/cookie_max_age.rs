@@ -0,0 +1,90 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::local::asynchronous::{Client, LocalResponse};
+use std::time::Duration;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+/// The 30 days remembered sessions last by default
+const REMEMBER_ME_MAX_AGE: &str = "Max-Age=2592000";
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+fn set_cookie<'r>(response: &'r LocalResponse<'_>, name: &str) -> Option<&'r str> {
+    let prefix = format!("{}=", name);
+    response
+        .headers()
+        .get("Set-Cookie")
+        .find(|cookie| cookie.starts_with(&prefix))
+}
+
+async fn client(idp: &MockIdp, context: WIDContext, login: Login) -> Client {
+    let rocket = rocket::ignite()
+        .manage(context.with_client(idp.client()))
+        .mount("/", routes![me])
+        .mount("/oauth2/wartid", wartid_client::rocket::routes(login));
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn context() -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+}
+
+#[rocket::async_test]
+async fn session_cookies_are_used_by_default() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, context(), Login::basic()).await;
+
+    let response = idp.log_in(&client).await;
+    for name in &["wartid_a", "wartid_r", "wartid_s"] {
+        assert!(!set_cookie(&response, name).unwrap().contains("Max-Age"));
+    }
+}
+
+#[rocket::async_test]
+async fn remembered_sessions_outlive_the_browser() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, context(), Login::basic().remember_me()).await;
+
+    let response = idp.log_in(&client).await;
+    for name in &["wartid_a", "wartid_r", "wartid_s"] {
+        assert!(set_cookie(&response, name)
+            .unwrap()
+            .contains(REMEMBER_ME_MAX_AGE));
+    }
+}
+
+#[rocket::async_test]
+async fn context_max_age_applies_to_every_session() {
+    let idp = MockIdp::start().await;
+    let context = context().with_cookie_max_age(Duration::from_secs(7 * 24 * 60 * 60));
+    let client = client(&idp, context, Login::basic()).await;
+
+    let response = idp.log_in(&client).await;
+    assert!(set_cookie(&response, "wartid_s")
+        .unwrap()
+        .contains("Max-Age=604800"));
+}
+
+#[rocket::async_test]
+async fn refreshed_cookies_stay_remembered() {
+    let idp = MockIdp::start().await;
+    idp.set_token_lifetime(-60);
+    let client = client(&idp, context(), Login::basic().remember_me()).await;
+    idp.log_in(&client).await;
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(idp.refresh_count(), 1);
+    assert!(set_cookie(&response, "wartid_a")
+        .unwrap()
+        .contains(REMEMBER_ME_MAX_AGE));
+}
@@ -0,0 +1,90 @@
+use rocket::local::asynchronous::{Client, LocalResponse};
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+fn set_cookie<'r>(response: &'r LocalResponse<'_>, name: &str) -> Option<&'r str> {
+    let prefix = format!("{}=", name);
+    response
+        .headers()
+        .get("Set-Cookie")
+        .find(|cookie| cookie.starts_with(&prefix))
+}
+
+async fn client(context: WIDContext) -> Client {
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn context(idp: &MockIdp) -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+}
+
+#[rocket::async_test]
+async fn flow_cookies_are_scoped_to_the_callback() {
+    let idp = MockIdp::start().await;
+    let client = client(context(&idp)).await;
+
+    let login = client.get("/oauth2/wartid/login").dispatch().await;
+    for name in &[
+        "wartid_auth_state",
+        "wartid_auth_scopes",
+        "wartid_auth_request_id",
+    ] {
+        assert!(set_cookie(&login, name)
+            .unwrap()
+            .contains("Path=/oauth2/wartid/callback"));
+    }
+
+    let callback = idp.authorize(login.headers().get_one("Location").unwrap());
+    let response = client
+        .get(callback.trim_start_matches(BASE_URL).to_string())
+        .dispatch()
+        .await;
+    assert!(set_cookie(&response, "wartid_s")
+        .unwrap()
+        .contains("Path=/"));
+    assert!(!set_cookie(&response, "wartid_s")
+        .unwrap()
+        .contains("Path=/oauth2"));
+
+    // Removed with the path they were set with, or browsers would keep them
+    assert!(set_cookie(&response, "wartid_auth_state")
+        .unwrap()
+        .contains("Path=/oauth2/wartid/callback"));
+}
+
+#[rocket::async_test]
+async fn cookie_paths_are_configurable() {
+    let idp = MockIdp::start().await;
+    let client = client(
+        context(&idp)
+            .with_flow_cookie_path("/oauth2")
+            .with_session_cookie_path("/app"),
+    )
+    .await;
+
+    let login = client.get("/oauth2/wartid/login").dispatch().await;
+    assert!(set_cookie(&login, "wartid_auth_state")
+        .unwrap()
+        .contains("Path=/oauth2;"));
+
+    let callback = idp.authorize(login.headers().get_one("Location").unwrap());
+    let response = client
+        .get(callback.trim_start_matches(BASE_URL).to_string())
+        .dispatch()
+        .await;
+    assert!(set_cookie(&response, "wartid_s")
+        .unwrap()
+        .contains("Path=/app"));
+}
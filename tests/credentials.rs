@@ -0,0 +1,14 @@
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls};
+
+#[test]
+fn secret_is_never_formatted() {
+    let credentials = WIDContextCredentials::new("client".into(), "hunter2".into());
+    assert!(!format!("{:?}", credentials).contains("hunter2"));
+    assert!(!format!("{:#?}", credentials).contains("hunter2"));
+
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("https://example.com"),
+        credentials,
+    );
+    assert!(!format!("{:?}", context).contains("hunter2"));
+}
@@ -0,0 +1,63 @@
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+/// Status of the callback once the user with the given userinfo approved the login
+async fn callback_status(userinfo: serde_json::Value) -> Status {
+    let idp = MockIdp::start().await;
+    idp.set_userinfo(userinfo);
+
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_allowed_email_domains(vec!["WP-Corp.eu.org"]);
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic().with_email()),
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = idp.log_in(&client).await;
+
+    response.status()
+}
+
+fn user(email: Option<&str>, email_verified: bool) -> serde_json::Value {
+    serde_json::json!({
+        "sub": "00000000-0000-0000-0000-000000000001",
+        "name": "Mock User",
+        "email": email,
+        "email_verified": email_verified,
+    })
+}
+
+#[rocket::async_test]
+async fn verified_emails_in_the_domains_are_allowed() {
+    let status = callback_status(user(Some("mock@wp-corp.eu.org"), true)).await;
+    assert_eq!(status, Status::TemporaryRedirect);
+}
+
+#[rocket::async_test]
+async fn other_domains_are_refused() {
+    let status = callback_status(user(Some("mock@evil.example.com"), true)).await;
+    assert_eq!(status, Status::Forbidden);
+
+    // Only the domain itself counts, not its subdomains nor lookalikes
+    let status = callback_status(user(Some("mock@evilwp-corp.eu.org"), true)).await;
+    assert_eq!(status, Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn unverified_or_missing_emails_are_refused() {
+    let status = callback_status(user(Some("mock@wp-corp.eu.org"), false)).await;
+    assert_eq!(status, Status::Forbidden);
+
+    let status = callback_status(user(None, false)).await;
+    assert_eq!(status, Status::Forbidden);
+}
@@ -0,0 +1,99 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+async fn client(idp: &MockIdp) -> Client {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic().with_form_post()),
+        );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+/// POSTs the authorization response of a callback URL to the callback, as WartID's auto-submitted
+/// form would
+async fn post_callback<'c>(client: &'c Client, callback: &str) -> LocalResponse<'c> {
+    let (path, params) = callback
+        .trim_start_matches(BASE_URL)
+        .split_once('?')
+        .unwrap();
+
+    client
+        .post(path.to_string())
+        .header(ContentType::Form)
+        .body(params)
+        .dispatch()
+        .await
+}
+
+#[rocket::async_test]
+async fn authorization_response_is_read_from_the_body() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    let login = client
+        .get("/oauth2/wartid/login?next=/settings")
+        .dispatch()
+        .await;
+    let authorize_url = login.headers().get_one("Location").unwrap();
+    assert!(authorize_url.contains("response_mode=form_post"));
+
+    let response = post_callback(&client, &idp.authorize(authorize_url)).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert_eq!(response.headers().get_one("Location"), Some("/settings"));
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+}
+
+#[rocket::async_test]
+async fn query_callbacks_are_still_accepted() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+}
+
+#[rocket::async_test]
+async fn flows_missing_their_state_are_restarted_keeping_the_page() {
+    let idp = MockIdp::start().await;
+    let other = client(&idp).await;
+    let client = client(&idp).await;
+
+    let login = client
+        .get("/oauth2/wartid/login?next=/settings")
+        .dispatch()
+        .await;
+    let callback = idp.authorize(login.headers().get_one("Location").unwrap());
+
+    // The state cookie expired before WartID posted the response back
+    let response = post_callback(&other, &callback).await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("http://localhost:8000/oauth2/wartid/login?next=%2Fsettings"),
+    );
+}
@@ -0,0 +1,83 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+#[get("/", rank = 1)]
+fn dashboard(session: &WartIDSession) -> String {
+    format!("Dashboard of {}", session.name)
+}
+
+#[get("/", rank = 2)]
+fn landing() -> &'static str {
+    "Landing page"
+}
+
+#[get("/owned", rank = 1)]
+fn owned(session: WartIDSession) -> String {
+    session.name
+}
+
+#[get("/owned", rank = 2)]
+fn owned_anonymous() -> &'static str {
+    "Anonymous"
+}
+
+async fn client(idp: &MockIdp, context: WIDContext) -> Client {
+    let rocket = rocket::ignite()
+        .manage(context.with_client(idp.client()))
+        .mount("/", routes![dashboard, landing, owned, owned_anonymous])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn context() -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+}
+
+#[rocket::async_test]
+async fn logged_out_requests_reach_the_anonymous_routes() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, context().with_forward_when_logged_out()).await;
+
+    let response = client.get("/").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some("Landing page")
+    );
+    let response = client.get("/owned").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Anonymous"));
+
+    idp.log_in(&client).await;
+
+    let response = client.get("/").dispatch().await;
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some("Dashboard of Mock User")
+    );
+    let response = client.get("/owned").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+}
+
+#[rocket::async_test]
+async fn logged_out_requests_fail_by_default() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, context()).await;
+
+    let response = client.get("/").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = client.get("/owned").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
@@ -0,0 +1,93 @@
+use rocket::http::Header;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls};
+
+async fn client(context: WIDContext) -> Client {
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn context() -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url("https://app.example.com"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+}
+
+/// Path to be redirected to after logging in, carried after the 20 random characters of the state
+/// and their 43 characters long MAC
+async fn redirection(
+    client: &Client,
+    next: &str,
+    headers: &[(&'static str, &'static str)],
+) -> String {
+    let mut request = client.get(format!(
+        "/oauth2/wartid/login?{}",
+        serde_urlencoded::to_string(&[("next", next)]).unwrap(),
+    ));
+    for (name, value) in headers {
+        request = request.header(Header::new(*name, *value));
+    }
+
+    let response = request.dispatch().await;
+    let location = response.headers().get_one("Location").unwrap();
+    let query = location.split_once('?').unwrap().1;
+    let state = serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+        .unwrap()
+        .into_iter()
+        .find(|(name, _)| name == "state")
+        .unwrap()
+        .1;
+
+    state[63..].to_string()
+}
+
+#[rocket::async_test]
+async fn absolute_next_on_the_host_is_accepted() {
+    let client = client(context()).await;
+
+    let next = redirection(
+        &client,
+        "https://app.example.com/dashboard?tab=1",
+        &[("Host", "app.example.com")],
+    )
+    .await;
+    assert_eq!(next, "/dashboard?tab=1");
+}
+
+#[rocket::async_test]
+async fn forwarded_headers_are_ignored_by_default() {
+    let client = client(context()).await;
+
+    let headers = [
+        ("Host", "backend:8000"),
+        ("X-Forwarded-Proto", "https"),
+        ("X-Forwarded-Host", "app.example.com"),
+    ];
+    let next = redirection(&client, "https://app.example.com/dashboard", &headers).await;
+    assert_eq!(next, "");
+}
+
+#[rocket::async_test]
+async fn trusted_forwarded_headers_give_the_origin() {
+    let client = client(context().with_trusted_forwarded_headers()).await;
+
+    let headers = [
+        ("Host", "backend:8000"),
+        ("X-Forwarded-Proto", "https, http"),
+        ("X-Forwarded-Host", "app.example.com"),
+    ];
+    let next = redirection(&client, "https://app.example.com/dashboard", &headers).await;
+    assert_eq!(next, "/dashboard");
+
+    let next = redirection(&client, "https://evil.example.com/dashboard", &headers).await;
+    assert_eq!(next, "");
+
+    let next = redirection(&client, "/settings", &headers).await;
+    assert_eq!(next, "/settings");
+}
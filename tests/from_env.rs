@@ -0,0 +1,44 @@
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{ConfigError, WIDContext};
+
+// The environment is shared by the whole process, so the cases run one after the other
+#[rocket::async_test]
+async fn context_from_env() {
+    std::env::remove_var("WARTID_CLIENT_ID");
+    std::env::set_var("WARTID_CLIENT_SECRET", "secret");
+    std::env::set_var("WARTID_BASE_URL", "https://app.example.com/");
+    std::env::remove_var("WARTID_ISSUER");
+
+    assert!(matches!(
+        WIDContext::from_env().await,
+        Err(ConfigError::MissingEnv("WARTID_CLIENT_ID"))
+    ));
+
+    std::env::set_var("WARTID_CLIENT_ID", "client");
+    let context = WIDContext::from_env().await.unwrap();
+    assert_eq!(context.credentials.client_id, "client");
+    assert_eq!(
+        context.urls.callback,
+        "https://app.example.com/oauth2/wartid/callback"
+    );
+
+    let idp = MockIdp::start().await;
+    std::env::set_var("WARTID_ISSUER", idp.base_url());
+    let context = WIDContext::from_env().await.unwrap();
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+    let login = client.get("/oauth2/wartid/login").dispatch().await;
+    let authorize_url = login.headers().get_one("Location").unwrap();
+    assert!(authorize_url.starts_with(&format!("{}/oauth2/authorize?", idp.base_url())));
+
+    std::env::set_var("WARTID_ISSUER", "http://127.0.0.1:1");
+    assert!(matches!(
+        WIDContext::from_env().await,
+        Err(ConfigError::Discovery(_))
+    ));
+}
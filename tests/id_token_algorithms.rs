@@ -0,0 +1,62 @@
+#[macro_use]
+extern crate rocket;
+
+use jsonwebtoken::Algorithm;
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{
+    IdentitySource, WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession,
+};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+/// Logs in through the mock with ID tokens signed with `algorithm`, and checks the identity
+/// they hold made it to the session
+async fn login(algorithm: Algorithm) {
+    let idp = MockIdp::start().await;
+    idp.set_id_token_algorithm(algorithm);
+
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_identity_source(IdentitySource::IdToken);
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+}
+
+#[rocket::async_test]
+async fn rs256_id_token_is_accepted() {
+    login(Algorithm::RS256).await;
+}
+
+#[rocket::async_test]
+async fn es256_id_token_is_accepted() {
+    login(Algorithm::ES256).await;
+}
+
+#[rocket::async_test]
+async fn eddsa_id_token_is_accepted() {
+    login(Algorithm::EdDSA).await;
+}
@@ -0,0 +1,51 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{
+    IdentitySource, WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession,
+};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+async fn callback_status(idp: &MockIdp) -> Status {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_identity_source(IdentitySource::IdToken);
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    idp.log_in(&client).await.status()
+}
+
+#[rocket::async_test]
+async fn id_token_of_the_issuer_is_accepted() {
+    let idp = MockIdp::start().await;
+
+    assert_eq!(callback_status(&idp).await, Status::TemporaryRedirect);
+}
+
+#[rocket::async_test]
+async fn id_token_of_another_issuer_is_rejected() {
+    let idp = MockIdp::start().await;
+    // Signed with the same keys, e.g. by another tenant of the provider
+    idp.set_id_token_issuer("https://other.example.com");
+
+    assert_eq!(callback_status(&idp).await, Status::Unauthorized);
+}
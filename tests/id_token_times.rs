@@ -0,0 +1,70 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use std::time::Duration;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{
+    IdentitySource, WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession,
+};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+/// Logs in through the mock with ID tokens issued at `issued_at` and valid from `not_before`,
+/// relative to now, returning the status of the callback
+async fn login(issued_at: i64, not_before: i64) -> Status {
+    let idp = MockIdp::start().await;
+    idp.set_id_token_times(issued_at, not_before);
+
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client().with_leeway(Duration::from_secs(30)))
+    .with_identity_source(IdentitySource::IdToken);
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let status = idp.log_in(&client).await.status();
+
+    if status == Status::TemporaryRedirect {
+        let response = client.get("/me").dispatch().await;
+        assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+    }
+
+    status
+}
+
+#[rocket::async_test]
+async fn not_before_just_past_is_accepted() {
+    assert_eq!(login(0, -5).await, Status::TemporaryRedirect);
+}
+
+#[rocket::async_test]
+async fn not_before_within_the_leeway_is_accepted() {
+    assert_eq!(login(0, 10).await, Status::TemporaryRedirect);
+}
+
+#[rocket::async_test]
+async fn not_before_in_the_future_is_rejected() {
+    assert_eq!(login(0, 120).await, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn issued_in_the_future_is_rejected() {
+    assert_eq!(login(120, 0).await, Status::Unauthorized);
+}
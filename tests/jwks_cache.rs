@@ -0,0 +1,32 @@
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{IdentitySource, WIDContext, WIDContextCredentials, WIDContextUrls};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+#[rocket::async_test]
+async fn jwks_is_fetched_once() {
+    let idp = MockIdp::start().await;
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_identity_source(IdentitySource::IdToken);
+
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    for _ in 0..3 {
+        assert_eq!(
+            idp.log_in(&client).await.status(),
+            Status::TemporaryRedirect
+        );
+    }
+    assert_eq!(idp.jwks_fetch_count(), 1);
+}
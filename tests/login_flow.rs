@@ -0,0 +1,534 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::{Cookie, CookieJar, Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::State;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wartid_client::handlers::Login;
+use wartid_client::metrics::MetricsSnapshot;
+use wartid_client::rocket::session_from_cookies;
+use wartid_client::testing::MockIdp;
+use wartid_client::{
+    UserInfoResponse, WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession,
+};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+#[get("/id")]
+fn id(session: &WartIDSession) -> String {
+    format!("{} {:?}", session.sub(), session.sub_uuid())
+}
+
+#[get("/name-from-cookies")]
+async fn name_from_cookies(cookies: &CookieJar<'_>, context: State<'_, WIDContext>) -> String {
+    match session_from_cookies(cookies, &context).await {
+        Ok(session) => session.name,
+        Err(err) => format!("{:?}", err),
+    }
+}
+
+#[get("/avatar")]
+fn avatar(session: &WartIDSession) -> Option<String> {
+    session.avatar_url().map(String::from)
+}
+
+async fn client(idp: &MockIdp) -> Client {
+    client_with_leeway(idp, Duration::from_secs(0)).await
+}
+
+async fn client_with_leeway(idp: &MockIdp, leeway: Duration) -> Client {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client().with_leeway(leeway));
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me, id, name_from_cookies, avatar])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn metrics(client: &Client) -> MetricsSnapshot {
+    client.rocket().state::<WIDContext>().unwrap().metrics()
+}
+
+/// `state` parameter of an authorization URL
+fn state_param(authorize_url: &str) -> String {
+    serde_urlencoded::from_str::<Vec<(String, String)>>(authorize_url.split_once('?').unwrap().1)
+        .unwrap()
+        .into_iter()
+        .find(|(name, _)| name == "state")
+        .unwrap()
+        .1
+}
+
+fn sets_cookie(response: &LocalResponse<'_>, name: &str) -> bool {
+    let prefix = format!("{}=", name);
+    response
+        .headers()
+        .get("Set-Cookie")
+        .any(|cookie| cookie.starts_with(&prefix))
+}
+
+#[rocket::async_test]
+async fn login_round_trip() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    let login = client.get("/oauth2/wartid/login").dispatch().await;
+    assert_eq!(login.status(), Status::TemporaryRedirect);
+    assert!(sets_cookie(&login, "wartid_auth_state"));
+
+    let authorize_url = login.headers().get_one("Location").unwrap();
+    assert!(authorize_url.starts_with(&format!("{}/oauth2/authorize?", idp.base_url())));
+
+    let callback = idp.authorize(authorize_url);
+    let response = client
+        .get(callback.trim_start_matches(BASE_URL).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert!(sets_cookie(&response, "wartid_a"));
+    assert!(sets_cookie(&response, "wartid_s"));
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+}
+
+#[rocket::async_test]
+async fn session_is_readable_outside_guards() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    let response = client.get("/name-from-cookies").dispatch().await;
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some("MissingAuthorization"),
+    );
+
+    idp.log_in(&client).await;
+
+    let response = client.get("/name-from-cookies").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+}
+
+#[rocket::async_test]
+async fn login_does_not_refresh_the_fresh_token() {
+    let idp = MockIdp::start().await;
+    // Tokens expiring within the leeway would be refreshed by any expiry check
+    idp.set_token_lifetime(10);
+    let client = client_with_leeway(&idp, Duration::from_secs(60)).await;
+
+    let response = idp.log_in(&client).await;
+
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert_eq!(idp.refresh_count(), 0);
+}
+
+#[rocket::async_test]
+async fn avatar_comes_from_the_picture_claim() {
+    let idp = MockIdp::start().await;
+    idp.set_userinfo(serde_json::json!({
+        "sub": "00000000-0000-0000-0000-000000000001",
+        "name": "Mock User",
+        "picture": "https://example.com/avatar.png",
+    }));
+    let client = client(&idp).await;
+
+    idp.log_in(&client).await;
+
+    let response = client.get("/avatar").dispatch().await;
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some("https://example.com/avatar.png"),
+    );
+}
+
+#[rocket::async_test]
+async fn opaque_subjects_are_accepted() {
+    let idp = MockIdp::start().await;
+    idp.set_userinfo(serde_json::json!({
+        "sub": "github|1234",
+        "name": "Mock User",
+    }));
+    let client = client(&idp).await;
+
+    idp.log_in(&client).await;
+
+    let response = client.get("/id").dispatch().await;
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some("github|1234 None"),
+    );
+}
+
+#[rocket::async_test]
+async fn wartid_subjects_are_uuids() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    idp.log_in(&client).await;
+
+    let response = client.get("/id").dispatch().await;
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some(
+            "00000000-0000-0000-0000-000000000001 \
+             Some(00000000-0000-0000-0000-000000000001)"
+        ),
+    );
+}
+
+#[rocket::async_test]
+async fn state_mismatch_is_rejected() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    let login = client.get("/oauth2/wartid/login").dispatch().await;
+    let callback = idp.authorize(login.headers().get_one("Location").unwrap());
+
+    // Same callback, for a flow started by someone else
+    let (path, query) = callback
+        .trim_start_matches(BASE_URL)
+        .split_once('?')
+        .unwrap();
+    let query = query
+        .split('&')
+        .map(|param| match param.strip_prefix("state=") {
+            Some(_) => "state=forged",
+            None => param,
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let response = client.get(format!("{}?{}", path, query)).dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(!sets_cookie(&response, "wartid_s"));
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn fixated_state_is_rejected() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    // Someone able to set cookies on the domain picks both the cookie and the state, without
+    // knowing the key of the MAC
+    let forged = format!("{}{}/", "a".repeat(20), "b".repeat(43));
+    let callback = idp.authorize(&format!(
+        "{}/oauth2/authorize?redirect_uri={}/oauth2/wartid/callback&state={}",
+        idp.base_url(),
+        BASE_URL,
+        forged,
+    ));
+
+    let response = client
+        .get(callback.trim_start_matches(BASE_URL).to_string())
+        .private_cookie(Cookie::new(
+            "wartid_auth_state",
+            format!("{}.{}", now(), forged),
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(!sets_cookie(&response, "wartid_s"));
+}
+
+#[rocket::async_test]
+async fn expired_flows_are_restarted() {
+    let idp = MockIdp::start().await;
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_login_flow_leeway(Duration::from_secs(60));
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+    // Untracked, so the callback only gets the state cookie set below
+    let client = Client::untracked(rocket).await.unwrap();
+
+    let login = client
+        .get("/oauth2/wartid/login?next=/settings")
+        .dispatch()
+        .await;
+    let authorize_url = login.headers().get_one("Location").unwrap();
+    let state = state_param(authorize_url);
+    let callback = idp.authorize(authorize_url);
+
+    // Started 10 minutes and 30 seconds ago: late, but within the leeway
+    let response = client
+        .get(callback.trim_start_matches(BASE_URL).to_string())
+        .private_cookie(Cookie::new(
+            "wartid_auth_state",
+            format!("{}.{}", now() - 630, state),
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert_eq!(response.headers().get_one("Location"), Some("/settings"));
+
+    // Started 12 minutes ago: the flow is restarted, keeping the page to land on
+    let callback = idp.authorize(authorize_url);
+    let response = client
+        .get(callback.trim_start_matches(BASE_URL).to_string())
+        .private_cookie(Cookie::new(
+            "wartid_auth_state",
+            format!("{}.{}", now() - 720, state),
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("http://localhost:8000/oauth2/wartid/login?next=%2Fsettings"),
+    );
+    assert!(!sets_cookie(&response, "wartid_s"));
+}
+
+#[rocket::async_test]
+async fn failed_callback_carries_the_request_id() {
+    let idp = MockIdp::start().await;
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_callback_error_redirect("/login-failed");
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let login = client
+        .get("/oauth2/wartid/login")
+        .header(Header::new("X-Request-Id", "proxy-42"))
+        .dispatch()
+        .await;
+    assert!(sets_cookie(&login, "wartid_auth_request_id"));
+
+    // The callback doesn't carry the header, the ID is the one of the request starting the flow
+    let response = client
+        .get("/oauth2/wartid/callback?code=unknown&state=forged")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("/login-failed?error=state_mismatch&request_id=proxy-42"),
+    );
+}
+
+#[rocket::async_test]
+async fn tokens_from_other_flows_are_rejected() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    client.get("/oauth2/wartid/login").dispatch().await;
+
+    let response = client
+        .get("/oauth2/wartid/callback?code=abc&state=def&access_token=ghi")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn default_landing_page_is_configurable() {
+    let idp = MockIdp::start().await;
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_post_login_redirect("/dashboard");
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.headers().get_one("Location"), Some("/dashboard"));
+
+    // The `next` parameter still wins
+    let response = idp
+        .log_in_from(&client, "/oauth2/wartid/login?next=/settings")
+        .await;
+    assert_eq!(response.headers().get_one("Location"), Some("/settings"));
+}
+
+#[rocket::async_test]
+async fn refused_users_get_no_session() {
+    let idp = MockIdp::start().await;
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_user_authorizer(|userinfo: &UserInfoResponse| userinfo.name() != "Mock User");
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert!(!sets_cookie(&response, "wartid_s"));
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn logged_in_users_can_skip_wartid() {
+    let idp = MockIdp::start().await;
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic().skip_if_logged_in()),
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    // Logged out users still go through WartID
+    let login = client.get("/oauth2/wartid/login").dispatch().await;
+    let authorize_url = login.headers().get_one("Location").unwrap();
+    assert!(authorize_url.starts_with(&idp.base_url()));
+
+    let callback = idp.authorize(authorize_url);
+    client
+        .get(callback.trim_start_matches(BASE_URL).to_string())
+        .dispatch()
+        .await;
+
+    let login = client
+        .get("/oauth2/wartid/login?next=/settings")
+        .dispatch()
+        .await;
+    assert_eq!(login.status(), Status::TemporaryRedirect);
+    assert_eq!(login.headers().get_one("Location"), Some("/settings"));
+    assert!(!sets_cookie(&login, "wartid_auth_state"));
+}
+
+#[rocket::async_test]
+async fn replayed_callback_keeps_users_logged_in() {
+    let idp = MockIdp::start().await;
+    let other = client(&idp).await;
+    let client = client(&idp).await;
+
+    let login = client
+        .get("/oauth2/wartid/login?next=/settings")
+        .dispatch()
+        .await;
+    let callback = idp.authorize(login.headers().get_one("Location").unwrap());
+    let callback = callback.trim_start_matches(BASE_URL).to_string();
+    let response = client.get(callback.clone()).dispatch().await;
+    assert_eq!(response.headers().get_one("Location"), Some("/settings"));
+
+    // Back button: the state cookie was removed and the code was used
+    let response = client.get(callback.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert_eq!(response.headers().get_one("Location"), Some("/settings"));
+    assert_eq!(metrics(&client).successful_callbacks, 1);
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+
+    // Without a session, the flow is restarted instead
+    let response = other.get(callback).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert!(response
+        .headers()
+        .get_one("Location")
+        .unwrap()
+        .contains("/oauth2/wartid/login"));
+}
+
+#[rocket::async_test]
+async fn redeemed_codes_only_redirect_logged_in_users() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    let login = client
+        .get("/oauth2/wartid/login?next=/settings")
+        .dispatch()
+        .await;
+    let authorize_url = login.headers().get_one("Location").unwrap();
+    let state = state_param(authorize_url);
+    let callback = idp.authorize(authorize_url);
+    let callback = callback.trim_start_matches(BASE_URL).to_string();
+    let response = client.get(callback.clone()).dispatch().await;
+    assert_eq!(response.headers().get_one("Location"), Some("/settings"));
+    assert_eq!(
+        metrics(&client),
+        MetricsSnapshot {
+            login_initiations: 1,
+            successful_callbacks: 1,
+            ..MetricsSnapshot::default()
+        }
+    );
+
+    // Replayed while the state cookie is still there, e.g. set by a flow started in another tab
+    let replay = || {
+        client.get(callback.clone()).private_cookie(Cookie::new(
+            "wartid_auth_state",
+            format!("{}.{}", now(), state),
+        ))
+    };
+    let response = replay().dispatch().await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert_eq!(response.headers().get_one("Location"), Some("/settings"));
+
+    assert_eq!(metrics(&client).successful_callbacks, 1);
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+
+    // Once logged out, the used code doesn't lead to the page anymore
+    client.post("/oauth2/wartid/logout").dispatch().await;
+    let response = replay().dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(!sets_cookie(&response, "wartid_s"));
+    assert_eq!(
+        metrics(&client),
+        MetricsSnapshot {
+            login_initiations: 1,
+            successful_callbacks: 1,
+            failed_callbacks: 1,
+            ..MetricsSnapshot::default()
+        }
+    );
+}
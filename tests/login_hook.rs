@@ -0,0 +1,125 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::{Client, LocalResponse};
+use std::sync::{Arc, Mutex};
+use wartid_client::handlers::Login;
+use wartid_client::hooks::{HookError, LoginHook};
+use wartid_client::testing::MockIdp;
+use wartid_client::{
+    TokenResponse, UserInfoResponse, WIDContext, WIDContextCredentials, WIDContextUrls,
+    WartIDSession,
+};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+/// What a hook got from a login
+struct RecordedLogin {
+    access_token: String,
+    refresh_token: Option<String>,
+    id_token: Option<String>,
+    userinfo: UserInfoResponse,
+}
+
+/// Records the responses of each login
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<RecordedLogin>>>);
+
+#[async_trait::async_trait]
+impl LoginHook for Recorder {
+    async fn on_login(
+        &self,
+        token: &TokenResponse,
+        userinfo: &UserInfoResponse,
+    ) -> Result<(), HookError> {
+        self.0.lock().unwrap().push(RecordedLogin {
+            access_token: token.access_token.clone(),
+            refresh_token: token.refresh_token.clone(),
+            id_token: token.id_token.clone(),
+            userinfo: userinfo.clone(),
+        });
+        Ok(())
+    }
+}
+
+/// Fails every login, e.g. as if the app's database was down
+struct Failing;
+
+#[async_trait::async_trait]
+impl LoginHook for Failing {
+    async fn on_login(&self, _: &TokenResponse, _: &UserInfoResponse) -> Result<(), HookError> {
+        Err("can't provision the user".into())
+    }
+}
+
+async fn client(idp: &MockIdp, hook: impl LoginHook + 'static) -> Client {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_login_hook(hook);
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn sets_cookie(response: &LocalResponse<'_>, name: &str) -> bool {
+    let prefix = format!("{}=", name);
+    response
+        .headers()
+        .get("Set-Cookie")
+        .any(|cookie| cookie.starts_with(&prefix))
+}
+
+#[rocket::async_test]
+async fn hooks_get_the_responses_of_each_login() {
+    let idp = MockIdp::start().await;
+    let recorder = Recorder::default();
+    let client = client(&idp, recorder.clone()).await;
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert!(sets_cookie(&response, "wartid_s"));
+
+    {
+        let logins = recorder.0.lock().unwrap();
+        assert_eq!(logins.len(), 1);
+
+        let login = &logins[0];
+        assert!(!login.access_token.is_empty());
+        assert!(login.refresh_token.is_some());
+        assert!(login.id_token.is_some());
+        assert_eq!(login.userinfo.sub(), "00000000-0000-0000-0000-000000000001");
+        assert_eq!(login.userinfo.name(), "Mock User");
+    }
+
+    // Requests of the session don't call it again
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(recorder.0.lock().unwrap().len(), 1);
+}
+
+#[rocket::async_test]
+async fn failing_hooks_abort_the_login() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, Failing).await;
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::InternalServerError);
+    assert!(!sets_cookie(&response, "wartid_s"));
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
@@ -0,0 +1,63 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+async fn client(context: WIDContext) -> Client {
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn context(idp: &MockIdp) -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+}
+
+#[rocket::async_test]
+async fn logout_only_accepts_post_by_default() {
+    let idp = MockIdp::start().await;
+    let client = client(context(&idp)).await;
+    idp.log_in(&client).await;
+
+    let response = client.get("/oauth2/wartid/logout").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+
+    client.post("/oauth2/wartid/logout").dispatch().await;
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn get_logout_can_be_kept() {
+    let idp = MockIdp::start().await;
+    let client = client(context(&idp).with_get_logout()).await;
+    idp.log_in(&client).await;
+
+    let response = client.get("/oauth2/wartid/logout").dispatch().await;
+    assert_eq!(response.headers().get_one("Location"), Some("/"));
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
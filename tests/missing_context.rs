@@ -0,0 +1,58 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::{WartIDSession, WartIDSessionOrRedirect};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+#[get("/profile")]
+fn profile(session: WartIDSessionOrRedirect) -> String {
+    session
+        .rocket()
+        .map(|session| session.name.clone())
+        .unwrap_or_default()
+}
+
+// The app forgot to manage a WIDContext
+async fn client() -> Client {
+    let rocket = rocket::ignite().mount("/", routes![me, profile]).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+#[rocket::async_test]
+async fn routes_fail_without_panicking() {
+    let client = client().await;
+
+    let response = client.get("/oauth2/wartid/login").dispatch().await;
+    assert_eq!(response.status(), Status::InternalServerError);
+
+    let response = client
+        .get("/oauth2/wartid/callback?code=abc&state=def")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::InternalServerError);
+
+    let response = client.post("/oauth2/wartid/logout").dispatch().await;
+    assert_eq!(response.status(), Status::InternalServerError);
+}
+
+#[rocket::async_test]
+async fn guards_fail_without_panicking() {
+    let client = client().await;
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::InternalServerError);
+
+    let response = client.get("/profile").dispatch().await;
+    assert_eq!(response.status(), Status::InternalServerError);
+}
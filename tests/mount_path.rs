@@ -0,0 +1,47 @@
+use wartid_client::{ConfigError, WIDContextUrls};
+
+#[test]
+fn base_url_routes_are_mounted_under_oauth2_wartid() {
+    let urls = WIDContextUrls::from_base_url("https://example.com");
+
+    assert_eq!(urls.mount_path().unwrap(), "/oauth2/wartid");
+}
+
+#[test]
+fn routes_at_the_root_are_mounted_at_slash() {
+    let urls = WIDContextUrls {
+        login: "https://example.com/login".into(),
+        callback: "https://example.com/callback".into(),
+        logout: "/logout".into(),
+    };
+
+    assert_eq!(urls.mount_path().unwrap(), "/");
+}
+
+#[test]
+fn diverging_paths_are_rejected() {
+    let urls = WIDContextUrls {
+        login: "https://example.com/auth/login".into(),
+        callback: "https://example.com/oauth2/wartid/callback".into(),
+        logout: "https://example.com/oauth2/wartid/logout".into(),
+    };
+
+    assert!(matches!(
+        urls.mount_path(),
+        Err(ConfigError::UnmountableUrls)
+    ));
+}
+
+#[test]
+fn unknown_route_names_are_rejected() {
+    let urls = WIDContextUrls {
+        login: "https://example.com/oauth2/wartid/login".into(),
+        callback: "https://example.com/oauth2/wartid/return".into(),
+        logout: "https://example.com/oauth2/wartid/logout".into(),
+    };
+
+    assert!(matches!(
+        urls.mount_path(),
+        Err(ConfigError::UnmountableUrls)
+    ));
+}
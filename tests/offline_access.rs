@@ -0,0 +1,84 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::{Client, LocalResponse};
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+async fn client(idp: &MockIdp, login: Login) -> Client {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount("/oauth2/wartid", wartid_client::rocket::routes(login));
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn set_cookie<'r>(response: &'r LocalResponse<'_>, name: &str) -> Option<&'r str> {
+    let prefix = format!("{}=", name);
+    response
+        .headers()
+        .get("Set-Cookie")
+        .find(|cookie| cookie.starts_with(&prefix))
+}
+
+#[rocket::async_test]
+async fn offline_logins_request_offline_access() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, Login::basic().offline()).await;
+
+    let login = client.get("/oauth2/wartid/login").dispatch().await;
+    let authorize_url = login.headers().get_one("Location").unwrap();
+    assert!(authorize_url.contains("offline_access"));
+}
+
+#[rocket::async_test]
+async fn sessions_without_refresh_token_end_with_the_access_token() {
+    let idp = MockIdp::start().await;
+    idp.set_issue_refresh_tokens(false);
+    idp.set_token_lifetime(-60);
+    let client = client(&idp, Login::basic()).await;
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(idp.refresh_count(), 0);
+
+    // The session was cleared rather than read again
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(idp.refresh_count(), 0);
+}
+
+#[rocket::async_test]
+async fn sessions_are_established_without_refresh_token() {
+    let idp = MockIdp::start().await;
+    idp.set_issue_refresh_tokens(false);
+    let client = client(&idp, Login::basic()).await;
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert!(set_cookie(&response, "wartid_a").is_some());
+    assert!(set_cookie(&response, "wartid_s").is_some());
+    assert!(set_cookie(&response, "wartid_r").map_or(true, |cookie| cookie.contains("Max-Age=0")));
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.unwrap(), "Mock User");
+    assert_eq!(idp.refresh_count(), 0);
+}
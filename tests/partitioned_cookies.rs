@@ -0,0 +1,52 @@
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::rocket::PartitionedCookies;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls};
+
+async fn login_cookies(context: WIDContext) -> Vec<String> {
+    let rocket = rocket::ignite()
+        .manage(context)
+        .attach(PartitionedCookies)
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = client.get("/oauth2/wartid/login").dispatch().await;
+    response
+        .headers()
+        .get("Set-Cookie")
+        .map(String::from)
+        .collect()
+}
+
+fn context() -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url("https://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+}
+
+#[rocket::async_test]
+async fn partitioned_cookies_are_cross_site() {
+    let cookies = login_cookies(context().with_partitioned_cookies()).await;
+
+    assert!(!cookies.is_empty());
+    for cookie in cookies {
+        assert!(cookie.contains("SameSite=None"), "{}", cookie);
+        assert!(cookie.contains("Secure"), "{}", cookie);
+        assert!(cookie.ends_with("; Partitioned"), "{}", cookie);
+    }
+}
+
+#[rocket::async_test]
+async fn cookies_are_not_partitioned_by_default() {
+    let cookies = login_cookies(context()).await;
+
+    assert!(!cookies.is_empty());
+    for cookie in cookies {
+        assert!(cookie.contains("SameSite=Lax"), "{}", cookie);
+        assert!(!cookie.contains("Partitioned"), "{}", cookie);
+    }
+}
@@ -0,0 +1,97 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{
+    wartid_protected, Scope, WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession,
+};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+#[wartid_protected]
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+#[wartid_protected]
+#[get("/greet/<greeting>")]
+async fn greet(greeting: String, session: &WartIDSession) -> Option<String> {
+    if greeting.is_empty() {
+        return None;
+    }
+
+    Some(format!("{} {}", greeting, session.name))
+}
+
+#[wartid_protected(scope = "email")]
+#[get("/email")]
+fn email(session: &WartIDSession) -> Option<String> {
+    session.email.clone()
+}
+
+async fn client(idp: &MockIdp, login: Login) -> Client {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me, greet, email])
+        .mount("/oauth2/wartid", wartid_client::rocket::routes(login));
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+#[rocket::async_test]
+async fn logged_out_users_are_redirected_to_login() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, Login::basic()).await;
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("http://localhost:8000/oauth2/wartid/login"),
+    );
+}
+
+#[rocket::async_test]
+async fn logged_in_users_reach_the_route() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp, Login::basic()).await;
+    idp.log_in(&client).await;
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+
+    let response = client.get("/greet/Hello").dispatch().await;
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some("Hello Mock User"),
+    );
+}
+
+#[rocket::async_test]
+async fn required_scope_is_checked() {
+    let idp = MockIdp::start().await;
+    let basic = client(&idp, Login::basic()).await;
+    idp.log_in(&basic).await;
+
+    let response = basic.get("/email").dispatch().await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let with_email = client(&idp, Login::basic().with_scope(Scope::Email)).await;
+    idp.log_in(&with_email).await;
+
+    let response = with_email.get("/email").dispatch().await;
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some("mock@example.com"),
+    );
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+use wartid_client::testing::MockIdp;
+use wartid_client::{Client, WartIDError};
+
+fn client(idp: &MockIdp, retries: u32) -> Client {
+    Client::builder()
+        .endpoints(idp.endpoints())
+        .retries(retries)
+        .build()
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn rate_limits_are_reported() {
+    let idp = MockIdp::start().await;
+    idp.rate_limit(1, Some(120));
+
+    let result = client(&idp, 0).request_userinfo_with_token("access").await;
+    assert!(matches!(
+        result,
+        Err(WartIDError::RateLimited {
+            retry_after: Some(retry_after)
+        }) if retry_after == Duration::from_secs(120)
+    ));
+}
+
+#[rocket::async_test]
+async fn short_rate_limits_are_retried() {
+    let idp = MockIdp::start().await;
+    idp.rate_limit(1, Some(0));
+
+    let result = client(&idp, 1).request_userinfo_with_token("access").await;
+    assert!(result.is_ok());
+}
+
+#[rocket::async_test]
+async fn long_rate_limits_are_not_waited_for() {
+    let idp = MockIdp::start().await;
+    idp.rate_limit(1, Some(120));
+
+    let result = client(&idp, 1).request_userinfo_with_token("access").await;
+    assert!(matches!(result, Err(WartIDError::RateLimited { .. })));
+}
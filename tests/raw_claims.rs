@@ -0,0 +1,57 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+#[get("/claims")]
+fn claims(session: &WartIDSession) -> String {
+    serde_json::Value::Object(session.raw_claims().clone()).to_string()
+}
+
+#[get("/audience")]
+fn audience(session: &WartIDSession) -> Option<String> {
+    session.claim("aud")
+}
+
+#[rocket::async_test]
+async fn every_claim_is_kept() {
+    let idp = MockIdp::start().await;
+    idp.set_userinfo(serde_json::json!({
+        "sub": "00000000-0000-0000-0000-000000000001",
+        "name": "Mock User",
+        "groups": ["admins", "devs"],
+        "department": { "name": "R&D", "floor": 3 },
+    }));
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![claims, audience])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    idp.log_in(&client).await;
+
+    let response = client.get("/claims").dispatch().await;
+    let claims: serde_json::Value =
+        serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(claims["name"], "Mock User");
+    assert_eq!(claims["groups"], serde_json::json!(["admins", "devs"]));
+    assert_eq!(claims["department"]["floor"], 3);
+    // Only in the ID token
+    assert_eq!(claims["aud"], "client");
+
+    let response = client.get("/audience").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("client"));
+}
@@ -0,0 +1,104 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::{Client, LocalResponse};
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{
+    WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession, WartIDSessionError,
+};
+
+#[get("/me")]
+fn me(session: Result<&WartIDSession, WartIDSessionError>) -> String {
+    match session {
+        Ok(session) => session.name.clone(),
+        Err(err) => format!("{:?}, logged out: {}", err, err.is_logged_out()),
+    }
+}
+
+/// Client logged in through the mock, with tokens refreshed on every request
+async fn logged_in(idp: &MockIdp) -> Client {
+    idp.set_token_lifetime(-60);
+
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+
+    client
+}
+
+/// Session cookies set by the response, including removals
+fn session_cookies<'r>(response: &'r LocalResponse<'_>) -> Vec<&'r str> {
+    response
+        .headers()
+        .get("Set-Cookie")
+        .filter(|cookie| {
+            ["wartid_a=", "wartid_r=", "wartid_s="]
+                .iter()
+                .any(|prefix| cookie.starts_with(prefix))
+        })
+        .collect()
+}
+
+#[rocket::async_test]
+async fn rejected_refreshes_log_out() {
+    let idp = MockIdp::start().await;
+    let client = logged_in(&idp).await;
+    idp.revoke_refresh_tokens();
+
+    let response = client.get("/me").dispatch().await;
+    let cookies = session_cookies(&response);
+    assert_eq!(cookies.len(), 3);
+    assert!(cookies.iter().all(|cookie| cookie.contains("Max-Age=0")));
+    assert_eq!(
+        response.into_string().await.unwrap(),
+        "RefreshRejected, logged out: true"
+    );
+    assert_eq!(idp.refresh_count(), 0);
+    let metrics = client.rocket().state::<WIDContext>().unwrap().metrics();
+    assert_eq!(metrics.refresh_failures, 1);
+
+    // The session was cleared rather than refreshed again
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(
+        response.into_string().await.unwrap(),
+        "MissingAuthorization, logged out: true"
+    );
+}
+
+#[rocket::async_test]
+async fn unreachable_token_endpoints_keep_the_session() {
+    let idp = MockIdp::start().await;
+    let client = logged_in(&idp).await;
+    idp.set_unavailable(true);
+
+    let response = client.get("/me").dispatch().await;
+    assert!(session_cookies(&response).is_empty());
+    assert_eq!(
+        response.into_string().await.unwrap(),
+        "Network, logged out: false"
+    );
+    assert_eq!(idp.refresh_count(), 0);
+
+    // The session is refreshed once WartID is back
+    idp.set_unavailable(false);
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.unwrap(), "Mock User");
+    assert_eq!(idp.refresh_count(), 1);
+}
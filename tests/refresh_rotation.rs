@@ -0,0 +1,95 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::rocket::TokensRefreshed;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+// Declared before the session guard, which it runs
+#[get("/refreshed")]
+fn refreshed(refreshed: TokensRefreshed, _session: &WartIDSession) -> String {
+    refreshed.0.to_string()
+}
+
+#[rocket::async_test]
+async fn rotated_refresh_tokens_are_persisted() {
+    let idp = MockIdp::start().await;
+    // Every request has to refresh the tokens, and every refresh revokes the previous token
+    idp.set_token_lifetime(-60);
+    idp.set_rotate_refresh_tokens(true);
+
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me, refreshed])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+
+    for _ in 0..3 {
+        let refreshes = idp.refresh_count();
+
+        let response = client.get("/me").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+
+        assert_eq!(idp.refresh_count(), refreshes + 1);
+    }
+
+    let metrics = client.rocket().state::<WIDContext>().unwrap().metrics();
+    assert_eq!(metrics.refreshes, 3);
+    assert_eq!(metrics.refresh_failures, 0);
+}
+
+#[rocket::async_test]
+async fn refreshes_are_exposed_to_the_request() {
+    let idp = MockIdp::start().await;
+    idp.set_token_lifetime(-60);
+
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me, refreshed])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    idp.log_in(&client).await;
+
+    let response = client.get("/refreshed").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("true"));
+
+    // Tokens are issued valid from now on, so only this request has to refresh them
+    idp.set_token_lifetime(3600);
+    client.get("/me").dispatch().await;
+
+    let response = client.get("/refreshed").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("false"));
+}
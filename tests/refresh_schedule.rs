@@ -0,0 +1,95 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use std::time::Duration;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+const BASE_URL: &str = "http://localhost:8000";
+const LEEWAY: Duration = Duration::from_secs(60);
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+/// Logs in through the mock, with tokens of the given lifetime
+async fn logged_in(idp: &MockIdp, opaque: bool, token_lifetime: i64) -> Client {
+    idp.set_opaque_tokens(opaque);
+    idp.set_token_lifetime(token_lifetime);
+
+    let mut wartid = idp.client().with_leeway(LEEWAY);
+    if opaque {
+        wartid = wartid.with_opaque_tokens();
+    }
+
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(wartid);
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+
+    client
+}
+
+/// Requests a page needing the session, returning the number of refreshes it triggered
+async fn refreshes_of_request(idp: &MockIdp, client: &Client) -> u64 {
+    let refreshes = idp.refresh_count();
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+
+    idp.refresh_count() - refreshes
+}
+
+#[rocket::async_test]
+async fn jwt_tokens_are_kept_until_the_leeway() {
+    let idp = MockIdp::start().await;
+    let client = logged_in(&idp, false, 3600).await;
+
+    assert_eq!(refreshes_of_request(&idp, &client).await, 0);
+    assert_eq!(refreshes_of_request(&idp, &client).await, 0);
+}
+
+#[rocket::async_test]
+async fn jwt_tokens_are_refreshed_within_the_leeway() {
+    let idp = MockIdp::start().await;
+    let client = logged_in(&idp, false, 30).await;
+
+    assert_eq!(refreshes_of_request(&idp, &client).await, 1);
+    assert_eq!(refreshes_of_request(&idp, &client).await, 1);
+}
+
+#[rocket::async_test]
+async fn opaque_tokens_are_kept_until_the_leeway() {
+    let idp = MockIdp::start().await;
+    let client = logged_in(&idp, true, 3600).await;
+
+    assert_eq!(refreshes_of_request(&idp, &client).await, 0);
+    assert_eq!(refreshes_of_request(&idp, &client).await, 0);
+}
+
+#[rocket::async_test]
+async fn opaque_tokens_are_refreshed_within_the_leeway() {
+    let idp = MockIdp::start().await;
+    let client = logged_in(&idp, true, 30).await;
+
+    assert_eq!(refreshes_of_request(&idp, &client).await, 1);
+    assert_eq!(refreshes_of_request(&idp, &client).await, 1);
+}
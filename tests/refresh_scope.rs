@@ -0,0 +1,32 @@
+use wartid_client::testing::MockIdp;
+use wartid_client::{Scope, WIDContext, WIDContextCredentials, WIDContextUrls};
+
+#[rocket::async_test]
+async fn refresh_narrows_the_scope() {
+    let idp = MockIdp::start().await;
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+    let client = idp.client();
+
+    let callback = idp.authorize(&format!(
+        "{}/oauth2/authorize?redirect_uri=http://localhost:8000/callback&state=state",
+        idp.base_url(),
+    ));
+    let code = callback
+        .split_once("code=")
+        .and_then(|(_, rest)| rest.split('&').next())
+        .unwrap();
+
+    let token = client.request_token(&context, code).await.unwrap();
+    let refresh_token = token.refresh_token.unwrap();
+
+    let narrowed = client
+        .request_token_refresh_with_scope(&context, &refresh_token, vec![Scope::Basic])
+        .await
+        .unwrap();
+    assert_eq!(narrowed.scope.as_deref(), Some("basic"));
+    assert_eq!(idp.refresh_count(), 1);
+}
@@ -0,0 +1,69 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+/// Client logged in through the mock, with tokens refreshed on every request
+async fn logged_in(idp: &MockIdp) -> Client {
+    idp.set_token_lifetime(-60);
+
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+
+    client
+}
+
+#[rocket::async_test]
+async fn refreshed_tokens_of_the_same_subject_are_accepted() {
+    let idp = MockIdp::start().await;
+    let client = logged_in(&idp).await;
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(idp.refresh_count(), 1);
+}
+
+#[rocket::async_test]
+async fn refreshed_tokens_of_another_subject_log_out() {
+    let idp = MockIdp::start().await;
+    let client = logged_in(&idp).await;
+
+    // The next tokens are issued for someone else
+    idp.set_userinfo(serde_json::json!({
+        "sub": "00000000-0000-0000-0000-000000000002",
+        "name": "Someone Else",
+    }));
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(idp.refresh_count(), 1);
+
+    // The session was cleared rather than refreshed again
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(idp.refresh_count(), 1);
+}
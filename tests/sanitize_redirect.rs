@@ -0,0 +1,45 @@
+use wartid_client::sanitize_redirect;
+
+#[test]
+fn same_origin_paths_are_accepted() {
+    assert_eq!(sanitize_redirect("/").as_deref(), Some("/"));
+    assert_eq!(sanitize_redirect("/ok").as_deref(), Some("/ok"));
+    assert_eq!(sanitize_redirect("/ok?x=1").as_deref(), Some("/ok?x=1"));
+    assert_eq!(sanitize_redirect("/a/b#c").as_deref(), Some("/a/b#c"));
+}
+
+#[test]
+fn protocol_relative_urls_are_rejected() {
+    assert_eq!(sanitize_redirect("//evil.com"), None);
+    assert_eq!(sanitize_redirect("///evil.com"), None);
+}
+
+#[test]
+fn backslashes_are_rejected() {
+    assert_eq!(sanitize_redirect("/\\evil.com"), None);
+    assert_eq!(sanitize_redirect("\\\\evil.com"), None);
+    assert_eq!(sanitize_redirect("/ok\\..\\evil"), None);
+}
+
+#[test]
+fn urls_with_a_scheme_are_rejected() {
+    assert_eq!(sanitize_redirect("https://evil"), None);
+    assert_eq!(sanitize_redirect("http:evil"), None);
+    assert_eq!(sanitize_redirect("javascript:alert(1)"), None);
+    assert_eq!(sanitize_redirect("data:text/html,evil"), None);
+}
+
+#[test]
+fn relative_paths_are_rejected() {
+    assert_eq!(sanitize_redirect(""), None);
+    assert_eq!(sanitize_redirect("evil.com"), None);
+    assert_eq!(sanitize_redirect("../ok"), None);
+}
+
+#[test]
+fn control_characters_are_rejected() {
+    // Browsers strip them, turning these into protocol-relative URLs
+    assert_eq!(sanitize_redirect("/\t/evil.com"), None);
+    assert_eq!(sanitize_redirect("/\n/evil.com"), None);
+    assert_eq!(sanitize_redirect("/\r/evil.com"), None);
+}
@@ -0,0 +1,32 @@
+use wartid_client::{EmailError, WartIDSession};
+
+fn session(scopes: &str, email: Option<&str>) -> WartIDSession {
+    serde_json::from_value(serde_json::json!({
+        "id": "00000000-0000-0000-0000-000000000001",
+        "name": "User",
+        "email": email,
+        "scopes": scopes,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn granted_email_is_returned() {
+    let session = session("basic email", Some("user@example.com"));
+
+    assert_eq!(session.email_or_err(), Ok("user@example.com"));
+}
+
+#[test]
+fn email_without_scope_is_not_granted() {
+    let session = session("basic", None);
+
+    assert_eq!(session.email_or_err(), Err(EmailError::NotGranted));
+}
+
+#[test]
+fn granted_scope_without_email_is_not_provided() {
+    let session = session("basic email", None);
+
+    assert_eq!(session.email_or_err(), Err(EmailError::NotProvided));
+}
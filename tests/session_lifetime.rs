@@ -0,0 +1,88 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::{Cookie, Status};
+use rocket::local::asynchronous::Client;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wartid_client::handlers::Login;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Requests `/me` with a cookie session logged in at `issued_at` and last seen at `last_seen`,
+/// omitted if `None`, with a 1 minute idle timeout and a 1 hour lifetime
+async fn get_me(issued_at: Option<i64>, last_seen: Option<i64>) -> Status {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(wartid_client::Client::default().with_opaque_tokens())
+    .with_idle_timeout(Duration::from_secs(60))
+    .with_absolute_lifetime(Duration::from_secs(60 * 60));
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let mut session = serde_json::json!({
+        "v": 1,
+        "id": "00000000-0000-0000-0000-000000000001",
+        "name": "Mock User",
+        "email": null,
+        "scopes": "basic",
+    });
+    if let Some(issued_at) = issued_at {
+        session["issued_at"] = issued_at.into();
+    }
+    if let Some(last_seen) = last_seen {
+        session["last_seen"] = last_seen.into();
+    }
+
+    client
+        .get("/me")
+        .private_cookie(Cookie::new("wartid_a", "opaque"))
+        .private_cookie(Cookie::new("wartid_s", session.to_string()))
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+async fn active_sessions_are_kept() {
+    let status = get_me(Some(now() - 30 * 60), Some(now() - 30)).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn idle_sessions_are_logged_out() {
+    let status = get_me(Some(now() - 30 * 60), Some(now() - 2 * 60)).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn sessions_past_their_lifetime_are_logged_out() {
+    let status = get_me(Some(now() - 2 * 60 * 60), Some(now() - 30)).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn sessions_saved_without_timestamps_are_kept() {
+    // Saved by a version of the crate which didn't track them
+    let status = get_me(None, None).await;
+    assert_eq!(status, Status::Ok);
+}
@@ -0,0 +1,110 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::local::asynchronous::{Client, LocalResponse};
+use wartid_client::handlers::Login;
+use wartid_client::seal::{SealError, SessionSealer};
+use wartid_client::store::StoredSession;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+fn stored_session() -> StoredSession {
+    serde_json::from_value(serde_json::json!({
+        "session": {
+            "v": 1,
+            "id": "00000000-0000-0000-0000-000000000001",
+            "name": "Mock User",
+            "email": null,
+            "scopes": "basic",
+        },
+        "access_token": "access",
+        "refresh_token": "refresh",
+    }))
+    .unwrap()
+}
+
+fn sets_cookie(response: &LocalResponse<'_>, name: &str) -> bool {
+    let prefix = format!("{}=", name);
+    response
+        .headers()
+        .get("Set-Cookie")
+        .any(|cookie| cookie.starts_with(&prefix))
+}
+
+#[test]
+fn sealed_sessions_can_be_opened() {
+    let sealer = SessionSealer::new(&[7; 32]);
+
+    let opened = sealer.open(&sealer.seal(&stored_session())).unwrap();
+    assert_eq!(opened.session, stored_session().session);
+    assert_eq!(opened.access_token, "access");
+    assert_eq!(opened.refresh_token.as_deref(), Some("refresh"));
+}
+
+#[test]
+fn sealing_twice_uses_different_nonces() {
+    let sealer = SessionSealer::new(&[7; 32]);
+
+    assert_ne!(
+        sealer.seal(&stored_session()),
+        sealer.seal(&stored_session())
+    );
+}
+
+#[test]
+fn sessions_sealed_with_another_key_are_rejected() {
+    let sealed = SessionSealer::new(&[7; 32]).seal(&stored_session());
+
+    let opened = SessionSealer::new(&[8; 32]).open(&sealed);
+    assert!(matches!(opened, Err(SealError::Decryption)));
+}
+
+#[test]
+fn tampered_sessions_are_rejected() {
+    let sealer = SessionSealer::new(&[7; 32]);
+
+    // A character of the ciphertext, past the nonce
+    let mut sealed = sealer.seal(&stored_session()).into_bytes();
+    sealed[40] = if sealed[40] == b'A' { b'B' } else { b'A' };
+    let opened = sealer.open(&String::from_utf8(sealed).unwrap());
+    assert!(matches!(opened, Err(SealError::Decryption)));
+
+    assert!(matches!(sealer.open("short"), Err(SealError::Malformed)));
+    assert!(matches!(
+        sealer.open("not base64!"),
+        Err(SealError::Malformed)
+    ));
+}
+
+#[rocket::async_test]
+async fn login_keeps_the_session_in_a_sealed_cookie() {
+    let idp = MockIdp::start().await;
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_session_sealer(&[7; 32]);
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let response = idp.log_in(&client).await;
+    assert!(sets_cookie(&response, "wartid_sealed"));
+    assert!(!sets_cookie(&response, "wartid_a"));
+    assert!(!sets_cookie(&response, "wartid_s"));
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+}
@@ -0,0 +1,127 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::{Cookie, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wartid_client::handlers::Login;
+use wartid_client::store::{MemorySessionStore, SessionStore, StoreError, StoredSession};
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+/// Memory store the test keeps a handle on, to see which sessions it holds
+#[derive(Clone, Default)]
+struct SharedStore {
+    inner: Arc<MemorySessionStore>,
+    ids: Arc<Mutex<HashSet<String>>>,
+}
+
+impl SharedStore {
+    fn ids(&self) -> HashSet<String> {
+        self.ids.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for SharedStore {
+    async fn get(&self, id: &str) -> Result<Option<StoredSession>, StoreError> {
+        self.inner.get(id).await
+    }
+
+    async fn set(&self, id: &str, session: &StoredSession) -> Result<(), StoreError> {
+        self.ids.lock().unwrap().insert(id.to_string());
+        self.inner.set(id, session).await
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), StoreError> {
+        self.ids.lock().unwrap().remove(id);
+        self.inner.remove(id).await
+    }
+}
+
+async fn client(idp: &MockIdp, store: &SharedStore) -> Client {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_session_store(store.clone());
+
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn sets_cookie(response: &LocalResponse<'_>, name: &str) -> bool {
+    let prefix = format!("{}=", name);
+    response
+        .headers()
+        .get("Set-Cookie")
+        .any(|cookie| cookie.starts_with(&prefix))
+}
+
+#[rocket::async_test]
+async fn tokens_are_kept_server_side() {
+    let idp = MockIdp::start().await;
+    let store = SharedStore::default();
+    let client = client(&idp, &store).await;
+
+    let response = idp.log_in(&client).await;
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+    assert!(sets_cookie(&response, "wartid_sid"));
+    for name in &["wartid_a", "wartid_r", "wartid_s"] {
+        assert!(!sets_cookie(&response, name), "{} set", name);
+    }
+    assert_eq!(store.ids().len(), 1);
+
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.into_string().await.as_deref(), Some("Mock User"));
+}
+
+#[rocket::async_test]
+async fn logout_removes_the_stored_session() {
+    let idp = MockIdp::start().await;
+    let store = SharedStore::default();
+    let client = client(&idp, &store).await;
+    idp.log_in(&client).await;
+
+    client.post("/oauth2/wartid/logout").dispatch().await;
+    let response = client.get("/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // The removal doesn't hold the response back
+    for _ in 0..100 {
+        if store.ids().is_empty() {
+            return;
+        }
+        rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("the stored session wasn't removed");
+}
+
+#[rocket::async_test]
+async fn unknown_session_ids_are_logged_out() {
+    let idp = MockIdp::start().await;
+    let store = SharedStore::default();
+    let client = client(&idp, &store).await;
+
+    let response = client
+        .get("/me")
+        .private_cookie(Cookie::new("wartid_sid", "unknown"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
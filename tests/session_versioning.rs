@@ -0,0 +1,88 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::http::{Cookie, Status, StatusClass};
+use rocket::local::asynchronous::Client;
+use rocket::response::Redirect;
+use wartid_client::handlers::Login;
+use wartid_client::{
+    WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession, WartIDSessionOrRedirect,
+};
+
+#[get("/me")]
+fn me(session: &WartIDSession) -> String {
+    session.name.clone()
+}
+
+#[get("/admin")]
+fn admin(session: WartIDSessionOrRedirect) -> Result<String, Redirect> {
+    Ok(session.rocket()?.name.clone())
+}
+
+async fn client() -> Client {
+    client_with(context()).await
+}
+
+fn context() -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(wartid_client::Client::default().with_opaque_tokens())
+}
+
+async fn client_with(context: WIDContext) -> Client {
+    let rocket = rocket::ignite()
+        .manage(context)
+        .mount("/", routes![me, admin])
+        .mount(
+            "/oauth2/wartid",
+            wartid_client::rocket::routes(Login::basic()),
+        );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+/// Requests `/me` with a cookie session holding the given serialized session
+async fn get_me(session: &str) -> (Status, Option<String>) {
+    let client = client().await;
+    let response = client
+        .get("/me")
+        .private_cookie(Cookie::new("wartid_a", "opaque"))
+        .private_cookie(Cookie::new("wartid_s", session.to_string()))
+        .dispatch()
+        .await;
+
+    (response.status(), response.into_string().await)
+}
+
+#[rocket::async_test]
+async fn unversioned_session_is_upgraded() {
+    let (status, name) = get_me(
+        r#"{"id":"00000000-0000-0000-0000-000000000001","name":"Legacy User","email":null,"scopes":"basic"}"#,
+    )
+    .await;
+
+    assert_eq!(status, Status::Ok);
+    assert_eq!(name.as_deref(), Some("Legacy User"));
+}
+
+#[rocket::async_test]
+async fn unreadable_session_requires_logging_in_again() {
+    let (status, _) = get_me(r#"{"v":1,"id":42}"#).await;
+
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn tampered_sealed_session_redirects_to_login() {
+    let client = client_with(context().with_session_sealer(&[0; 32])).await;
+
+    let response = client
+        .get("/admin")
+        .cookie(Cookie::new("wartid_sealed", "tampered"))
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status().class(), StatusClass::Redirection);
+}
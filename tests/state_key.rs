@@ -0,0 +1,90 @@
+use hmac::{Hmac, Mac, NewMac};
+use rocket::http::{Cookie, Status};
+use rocket::local::asynchronous::Client;
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls};
+
+const BASE_URL: &str = "http://localhost:8000";
+const STATE_KEY: [u8; 32] = [7; 32];
+
+async fn client(idp: &MockIdp) -> Client {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_state_key(&STATE_KEY);
+
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+
+    Client::tracked(rocket).await.unwrap()
+}
+
+/// State as the app builds it: the random part, its MAC, then the redirection
+fn sign_state(key: &[u8], redirect: &str) -> String {
+    let random = "a".repeat(20);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(random.as_bytes());
+    mac.update(redirect.as_bytes());
+    let tag = mac.finalize().into_bytes();
+
+    format!(
+        "{}{}{}",
+        random,
+        base64::encode_config(tag, base64::URL_SAFE_NO_PAD),
+        redirect
+    )
+}
+
+/// Requests the callback of a flow with the given state, as if it was started by the client
+async fn callback(idp: &MockIdp, client: &Client, state: &str) -> Status {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let callback = idp.authorize(&format!(
+        "{}/oauth2/authorize?redirect_uri={}/oauth2/wartid/callback&state={}",
+        idp.base_url(),
+        BASE_URL,
+        state,
+    ));
+
+    client
+        .get(callback.trim_start_matches(BASE_URL).to_string())
+        .private_cookie(Cookie::new(
+            "wartid_auth_state",
+            format!("{}.{}", now, state),
+        ))
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+async fn states_signed_with_the_state_key_are_accepted() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    let state = sign_state(&STATE_KEY, "/settings");
+    assert_eq!(
+        callback(&idp, &client, &state).await,
+        Status::TemporaryRedirect
+    );
+}
+
+#[rocket::async_test]
+async fn states_signed_with_the_client_secret_are_refused() {
+    let idp = MockIdp::start().await;
+    let client = client(&idp).await;
+
+    // WartID knows the client secret, so it mustn't be able to sign states
+    let state = sign_state(b"secret", "/settings");
+    assert_eq!(callback(&idp, &client, &state).await, Status::Unauthorized);
+}
@@ -0,0 +1,53 @@
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use wartid_client::handlers::Login;
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls};
+
+const BASE_URL: &str = "http://localhost:8000";
+
+fn set_cookie<'r>(response: &'r LocalResponse<'_>, name: &str) -> Option<&'r str> {
+    let prefix = format!("{}=", name);
+    response
+        .headers()
+        .get("Set-Cookie")
+        .find(|cookie| cookie.starts_with(&prefix))
+}
+
+#[rocket::async_test]
+async fn session_cookies_are_strict_and_flow_cookies_lax() {
+    let idp = MockIdp::start().await;
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url(BASE_URL),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client())
+    .with_strict_cookies();
+    let rocket = rocket::ignite().manage(context).mount(
+        "/oauth2/wartid",
+        wartid_client::rocket::routes(Login::basic()),
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let login = client
+        .get("/oauth2/wartid/login?next=/dashboard%3Fa%3D1%26b%3D2")
+        .dispatch()
+        .await;
+    let state = set_cookie(&login, "wartid_auth_state").unwrap();
+    assert!(state.contains("SameSite=Lax"));
+
+    let callback = idp.authorize(login.headers().get_one("Location").unwrap());
+    let response = client
+        .get(callback.trim_start_matches(BASE_URL).to_string())
+        .dispatch()
+        .await;
+    assert!(set_cookie(&response, "wartid_s")
+        .unwrap()
+        .contains("SameSite=Strict"));
+
+    // A same-site navigation, rather than a redirection which would drop the strict cookies
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let page = response.into_string().await.unwrap();
+    assert!(page.contains("content=\"0; url=/dashboard?a=1&amp;b=2\""));
+}
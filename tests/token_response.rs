@@ -0,0 +1,33 @@
+use wartid_client::TokenResponse;
+
+#[test]
+fn full_response_is_parsed() {
+    let token: TokenResponse = serde_json::from_str(
+        r#"{
+            "access_token": "access",
+            "token_type": "Bearer",
+            "expires_in": 3600,
+            "refresh_token": "refresh",
+            "id_token": "id",
+            "scope": "basic email"
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(token.access_token, "access");
+    assert_eq!(token.token_type(), "Bearer");
+    assert_eq!(token.expires_in(), Some(3600));
+    assert_eq!(token.refresh_token.as_deref(), Some("refresh"));
+    assert_eq!(token.id_token.as_deref(), Some("id"));
+    assert_eq!(token.scope.as_deref(), Some("basic email"));
+}
+
+#[test]
+fn optional_fields_default_to_none() {
+    let token: TokenResponse = serde_json::from_str(r#"{"access_token": "access"}"#).unwrap();
+
+    assert_eq!(token.expires_in(), None);
+    assert_eq!(token.refresh_token, None);
+    assert_eq!(token.id_token, None);
+    assert_eq!(token.scope, None);
+}
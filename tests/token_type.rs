@@ -0,0 +1,47 @@
+use wartid_client::testing::MockIdp;
+use wartid_client::{WIDContext, WIDContextCredentials, WIDContextUrls, WartIDError};
+
+async fn request_token(idp: &MockIdp) -> Result<wartid_client::TokenResponse, WartIDError> {
+    let context = WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(idp.client());
+
+    let callback = idp.authorize(&format!(
+        "{}/oauth2/authorize?redirect_uri=http://localhost:8000/callback&state=state",
+        idp.base_url(),
+    ));
+    let code = callback
+        .split_once("code=")
+        .and_then(|(_, rest)| rest.split('&').next())
+        .unwrap();
+
+    idp.client().request_token(&context, code).await
+}
+
+#[rocket::async_test]
+async fn bearer_is_case_insensitive() {
+    let idp = MockIdp::start().await;
+
+    idp.set_token_type("bearer");
+    let token = request_token(&idp).await.unwrap();
+    assert_eq!(token.token_type(), "bearer");
+
+    idp.set_token_type("BEARER");
+    assert!(request_token(&idp).await.is_ok());
+}
+
+#[rocket::async_test]
+async fn other_token_types_are_rejected() {
+    let idp = MockIdp::start().await;
+    idp.set_token_type("DPoP");
+
+    match request_token(&idp).await {
+        Err(WartIDError::UnsupportedTokenType(token_type)) => assert_eq!(token_type, "DPoP"),
+        other => panic!(
+            "unexpected result: {:?}",
+            other.map(|token| token.access_token)
+        ),
+    }
+}
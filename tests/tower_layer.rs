@@ -0,0 +1,94 @@
+use http::{Request, Response, StatusCode};
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+use wartid_client::testing::MockIdp;
+use wartid_client::tower::WartIDLayer;
+use wartid_client::{
+    BearerTokens, WIDContext, WIDContextCredentials, WIDContextUrls, WartIDSession,
+};
+
+/// Responds with the name of the user, or "anonymous"
+#[derive(Clone)]
+struct Greet;
+
+impl Service<Request<()>> for Greet {
+    type Response = Response<String>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<()>) -> Self::Future {
+        let name = match request.extensions().get::<WartIDSession>() {
+            Some(session) => session.name.clone(),
+            None => "anonymous".into(),
+        };
+
+        ready(Ok(Response::new(name)))
+    }
+}
+
+fn context(idp: &MockIdp, bearer_tokens: BearerTokens) -> Arc<WIDContext> {
+    Arc::new(
+        WIDContext::new(
+            WIDContextUrls::from_base_url("http://localhost:8000"),
+            WIDContextCredentials::new("client".into(), "secret".into()),
+        )
+        .with_client(idp.client())
+        .with_bearer_tokens(bearer_tokens),
+    )
+}
+
+fn request(bearer: Option<&str>) -> Request<()> {
+    let mut request = Request::builder().uri("/");
+    if let Some(bearer) = bearer {
+        request = request.header("Authorization", format!("Bearer {}", bearer));
+    }
+    request.body(()).unwrap()
+}
+
+#[tokio::test]
+async fn bearer_token_is_authenticated() {
+    let idp = MockIdp::start().await;
+    let mut service = WartIDLayer::required(context(&idp, BearerTokens::Fallback)).layer(Greet);
+
+    let token = idp.issue_access_token("client", "basic");
+    let response = service.call(request(Some(&token))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body(), "Mock User");
+}
+
+#[tokio::test]
+async fn bearer_tokens_are_ignored_by_default() {
+    let idp = MockIdp::start().await;
+    let mut service = WartIDLayer::required(context(&idp, BearerTokens::default())).layer(Greet);
+
+    let token = idp.issue_access_token("client", "basic");
+    let response = service.call(request(Some(&token))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn required_layer_rejects_anonymous_requests() {
+    let idp = MockIdp::start().await;
+    let mut service = WartIDLayer::required(context(&idp, BearerTokens::default())).layer(Greet);
+
+    let response = service.call(request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn optional_layer_passes_anonymous_requests_through() {
+    let idp = MockIdp::start().await;
+    let mut service = WartIDLayer::optional(context(&idp, BearerTokens::default())).layer(Greet);
+
+    let response = service.call(request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body(), "anonymous");
+}
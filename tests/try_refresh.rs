@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wartid_client::{
+    Authorization, Client, TokenRefresher, TokenResponse, WIDContext, WIDContextCredentials,
+    WIDContextUrls, WartIDError,
+};
+
+/// Refresher answering every refresh with the same canned response
+struct FakeRefresher {
+    response: fn() -> Result<TokenResponse, WartIDError>,
+    calls: AtomicUsize,
+}
+
+impl FakeRefresher {
+    fn new(response: fn() -> Result<TokenResponse, WartIDError>) -> Self {
+        Self {
+            response,
+            calls: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenRefresher for FakeRefresher {
+    async fn refresh(
+        &self,
+        _: &WIDContext,
+        refresh_token: &str,
+    ) -> Result<TokenResponse, WartIDError> {
+        assert_eq!(refresh_token, "refresh-1");
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        (self.response)()
+    }
+}
+
+fn token(refresh_token: Option<&str>) -> TokenResponse {
+    serde_json::from_value(serde_json::json!({
+        "access_token": "access-2",
+        "token_type": "Bearer",
+        "expires_in": 3600,
+        "refresh_token": refresh_token,
+    }))
+    .unwrap()
+}
+
+fn context() -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+}
+
+/// Authorization whose access token expired
+fn expired() -> Authorization<'static> {
+    Authorization::new("access-1", Some("refresh-1")).with_expiry(Some(0))
+}
+
+#[rocket::async_test]
+async fn expired_token_is_refreshed_and_rotated() {
+    let refresher = FakeRefresher::new(|| Ok(token(Some("refresh-2"))));
+    let mut authorization = expired();
+
+    authorization
+        .try_refresh_with(&context(), &Client::default(), &refresher)
+        .await
+        .unwrap();
+
+    let (access_token, refresh_token, expires_at) = authorization.take_updated().unwrap();
+    assert_eq!(access_token, "access-2");
+    assert_eq!(refresh_token, "refresh-2");
+    assert!(expires_at.is_some());
+}
+
+#[rocket::async_test]
+async fn refresh_token_is_kept_when_not_rotated() {
+    let refresher = FakeRefresher::new(|| Ok(token(None)));
+    let mut authorization = expired();
+
+    authorization
+        .try_refresh_with(&context(), &Client::default(), &refresher)
+        .await
+        .unwrap();
+
+    let (_, refresh_token, _) = authorization.take_updated().unwrap();
+    assert_eq!(refresh_token, "refresh-1");
+}
+
+#[rocket::async_test]
+async fn refresh_failure_is_propagated() {
+    let refresher = FakeRefresher::new(|| Err(WartIDError::InvalidGrant));
+    let mut authorization = expired();
+
+    let result = authorization
+        .try_refresh_with(&context(), &Client::default(), &refresher)
+        .await;
+
+    assert!(matches!(result, Err(WartIDError::InvalidGrant)));
+    assert_eq!(authorization.take_updated(), None);
+}
+
+#[rocket::async_test]
+async fn valid_token_is_not_refreshed() {
+    let refresher = FakeRefresher::new(|| Ok(token(Some("refresh-2"))));
+    let mut authorization = Authorization::new("access-1", Some("refresh-1"))
+        .with_expiry(Some(chrono::Utc::now().timestamp() + 3600));
+
+    authorization
+        .try_refresh_with(&context(), &Client::default(), &refresher)
+        .await
+        .unwrap();
+
+    assert_eq!(authorization.take_updated(), None);
+    assert_eq!(refresher.calls.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,88 @@
+use std::time::Duration;
+use wartid_client::testing::MockIdp;
+use wartid_client::{Authorization, Client, WIDContext, WIDContextCredentials, WIDContextUrls};
+
+fn context(client: Client) -> WIDContext {
+    WIDContext::new(
+        WIDContextUrls::from_base_url("http://localhost:8000"),
+        WIDContextCredentials::new("client".into(), "secret".into()),
+    )
+    .with_client(client)
+}
+
+fn cached_client(idp: &MockIdp, capacity: usize, ttl: Duration) -> Client {
+    Client::builder()
+        .endpoints(idp.endpoints())
+        .userinfo_cache(capacity, ttl)
+        .build()
+        .unwrap()
+}
+
+/// Name returned for the subject, from the cache or WartID
+async fn name(client: &Client, context: &WIDContext, sub: &str) -> String {
+    let expires_at = chrono::Utc::now().timestamp() + 3600;
+    let mut authorization = Authorization::new("access", None).with_expiry(Some(expires_at));
+
+    client
+        .userinfo_cached(context, &mut authorization, sub)
+        .await
+        .unwrap()
+        .name()
+        .to_string()
+}
+
+fn set_user(idp: &MockIdp, sub: &str, name: &str) {
+    idp.set_userinfo(serde_json::json!({ "sub": sub, "name": name }));
+}
+
+#[rocket::async_test]
+async fn fresh_entries_are_served_until_invalidated() {
+    let idp = MockIdp::start().await;
+    let client = cached_client(&idp, 10, Duration::from_secs(60));
+    let context = context(client.clone());
+
+    set_user(&idp, "alice", "Alice");
+    assert_eq!(name(&client, &context, "alice").await, "Alice");
+
+    set_user(&idp, "alice", "Alice Renamed");
+    assert_eq!(name(&client, &context, "alice").await, "Alice");
+
+    client.invalidate_userinfo("alice");
+    assert_eq!(name(&client, &context, "alice").await, "Alice Renamed");
+}
+
+#[rocket::async_test]
+async fn entries_expire() {
+    let idp = MockIdp::start().await;
+    let client = cached_client(&idp, 10, Duration::from_millis(0));
+    let context = context(client.clone());
+
+    set_user(&idp, "alice", "Alice");
+    assert_eq!(name(&client, &context, "alice").await, "Alice");
+
+    set_user(&idp, "alice", "Alice Renamed");
+    assert_eq!(name(&client, &context, "alice").await, "Alice Renamed");
+}
+
+#[rocket::async_test]
+async fn least_recently_used_entries_are_evicted() {
+    let idp = MockIdp::start().await;
+    let client = cached_client(&idp, 2, Duration::from_secs(60));
+    let context = context(client.clone());
+
+    set_user(&idp, "alice", "Alice");
+    name(&client, &context, "alice").await;
+    set_user(&idp, "bob", "Bob");
+    name(&client, &context, "bob").await;
+
+    // Alice is used again, so Bob is the one evicted by Carol
+    name(&client, &context, "alice").await;
+    set_user(&idp, "carol", "Carol");
+    name(&client, &context, "carol").await;
+
+    set_user(&idp, "alice", "Alice Renamed");
+    assert_eq!(name(&client, &context, "alice").await, "Alice");
+
+    set_user(&idp, "bob", "Bob Renamed");
+    assert_eq!(name(&client, &context, "bob").await, "Bob Renamed");
+}